@@ -0,0 +1,231 @@
+use crate::MatchLine;
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use regex::Regex;
+
+/// The casing conventions searched for by --case-variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseStyle {
+    Snake,
+    Camel,
+    Pascal,
+    ScreamingSnake,
+    Kebab,
+}
+
+impl CaseStyle {
+    pub const ALL: [CaseStyle; 5] = [
+        CaseStyle::Snake,
+        CaseStyle::Camel,
+        CaseStyle::Pascal,
+        CaseStyle::ScreamingSnake,
+        CaseStyle::Kebab,
+    ];
+
+    /// Join lowercase words into an identifier using this casing
+    pub fn apply(self, words: &[String]) -> String {
+        match self {
+            CaseStyle::Snake => words.join("_"),
+            CaseStyle::Kebab => words.join("-"),
+            CaseStyle::ScreamingSnake => words.iter().map(|w| w.to_uppercase()).join("_"),
+            CaseStyle::Pascal => words.iter().map(|w| capitalize(w)).collect(),
+            CaseStyle::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| if i == 0 { w.clone() } else { capitalize(w) })
+                .collect(),
+        }
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Split an identifier in any of the supported casings into lowercase words.
+/// Ex: "HTTPServer_id" -> ["http", "server", "id"]
+pub fn split_words(ident: &str) -> Vec<String> {
+    let chars: Vec<char> = ident.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        if c.is_uppercase() && !current.is_empty() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            // "userId" splits before 'I', "HTTPServer" splits before 'S'
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower) {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// Every distinct casing of `word`. Styles which produce the same text as an
+/// earlier one (e.g. snake and camel for a single word) are dropped.
+pub fn variants(word: &str) -> Vec<(CaseStyle, String)> {
+    let words = split_words(word);
+    CaseStyle::ALL
+        .iter()
+        .map(|&style| (style, style.apply(&words)))
+        .filter(|(_, v)| !v.is_empty())
+        .unique_by(|(_, v)| v.clone())
+        .collect()
+}
+
+/// A regex alternation matching any of the variants as a whole identifier, so
+/// that e.g. "user_ids" and "get_user_id" are left alone
+pub fn search_pattern(variants: &[(CaseStyle, String)]) -> String {
+    let alternation = variants
+        .iter()
+        .map(|(_, v)| v)
+        .sorted_by_key(|v| std::cmp::Reverse(v.len()))
+        .map(|v| regex::escape(v))
+        .join("|");
+    format!(r"\b(?:{})\b", alternation)
+}
+
+/// Annotate each line with the variants found on it, like "found: userId"
+pub fn tag_variants(match_lines: &mut [MatchLine], word: &str) -> Result<()> {
+    let re = Regex::new(&search_pattern(&variants(word))).context("building case variant regex")?;
+    for m in match_lines.iter_mut() {
+        let found: Vec<&str> = re
+            .find_iter(&m.original_content)
+            .map(|f| f.as_str())
+            .unique()
+            .collect();
+        if !found.is_empty() {
+            m.annotation = Some(format!("found: {}", found.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+/// Pre-fill each line with every variant replaced by the same-cased form of
+/// `new_name`
+pub fn prefill_replacements(
+    match_lines: &mut [MatchLine],
+    word: &str,
+    new_name: &str,
+) -> Result<()> {
+    let variants = variants(word);
+    let re = Regex::new(&search_pattern(&variants)).context("building case variant regex")?;
+    let new_words = split_words(new_name);
+
+    for m in match_lines.iter_mut() {
+        let replaced = re.replace_all(&m.original_content, |caps: &regex::Captures| {
+            let found = &caps[0];
+            variants
+                .iter()
+                .find(|(_, v)| v == found)
+                .map(|(style, _)| style.apply(&new_words))
+                .unwrap_or_else(|| found.to_string())
+        });
+        if replaced != m.original_content {
+            m.replacement = Some(replaced.into_owned());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileAlias;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("user_id"), vec!["user", "id"]);
+        assert_eq!(split_words("userId"), vec!["user", "id"]);
+        assert_eq!(split_words("USER_ID"), vec!["user", "id"]);
+        assert_eq!(split_words("HTTPServer"), vec!["http", "server"]);
+        assert_eq!(split_words("user-id2"), vec!["user", "id2"]);
+    }
+
+    #[test]
+    fn test_variants() {
+        let found: Vec<String> = variants("userId").into_iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            found,
+            vec!["user_id", "userId", "UserId", "USER_ID", "user-id"]
+        );
+
+        // Single words collapse snake, camel and kebab
+        let found: Vec<String> = variants("user").into_iter().map(|(_, v)| v).collect();
+        assert_eq!(found, vec!["user", "User", "USER"]);
+    }
+
+    #[test]
+    fn test_search_pattern() {
+        let re = Regex::new(&search_pattern(&variants("user_id"))).unwrap();
+        let found: Vec<&str> = re
+            .find_iter("user_id userId get_user_id user_ids UserIdx USER_ID")
+            .map(|m| m.as_str())
+            .collect();
+        assert_eq!(found, vec!["user_id", "userId", "USER_ID"]);
+    }
+
+    #[test]
+    fn test_tag_variants() {
+        let alias = FileAlias::new(&['A']);
+        let mut lines = vec![
+            MatchLine::new(alias, 1, "let userId = row.user_id + userId;".into()),
+            MatchLine::new(alias, 2, "let user_ids = 1;".into()),
+        ];
+        tag_variants(&mut lines, "user_id").unwrap();
+        assert_eq!(
+            lines[0].annotation.as_deref(),
+            Some("found: userId, user_id")
+        );
+        assert_eq!(lines[1].annotation, None);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut lines = vec![
+            MatchLine::new(
+                FileAlias::new(&['A']),
+                1,
+                "let userId = row.user_id;".into(),
+            ),
+            MatchLine::new(
+                FileAlias::new(&['A']),
+                2,
+                "const USER_ID: &str = \"\";".into(),
+            ),
+            MatchLine::new(
+                FileAlias::new(&['A']),
+                3,
+                "let user_ids = get_user_id();".into(),
+            ),
+        ];
+        prefill_replacements(&mut lines, "user_id", "accountKey").unwrap();
+
+        assert_eq!(
+            lines[0].replacement.as_deref(),
+            Some("let accountKey = row.account_key;")
+        );
+        assert_eq!(
+            lines[1].replacement.as_deref(),
+            Some("const ACCOUNT_KEY: &str = \"\";")
+        );
+        // Longer identifiers which contain a variant are left alone
+        assert_eq!(lines[2].replacement, None);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::file_loader::{get_absolute_base, load_locations, read_input};
+use crate::filters::case_insensitive;
+use crate::locations::{LineSpan, Location};
+use crate::prefix::relative_path;
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine, case_variants};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+
+/// Load the result lines of a VS Code Search Editor file. Context lines are
+/// skipped, and each line is read from disk rather than from the file's
+/// preview, which VS Code truncates for long lines.
+pub fn load_from_code_search(
+    search_path: &Utf8PathBuf,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
+    let content = read_input(search_path)?;
+    let locations = parse_code_search(&content)?;
+    let (matches, files) = load_locations(locations, args, filters)?;
+    Ok((matches, files, format!("Code search: {}", search_path)))
+}
+
+/// Save the matches as a `.code-search` file which VS Code can open
+pub fn save_code_search(
+    out_path: &Utf8PathBuf,
+    args: &Args,
+    match_lines: &[MatchLine],
+    files: &BTreeMap<FileAlias, FileInfo>,
+) -> Result<()> {
+    let base = get_absolute_base(args)?;
+    let text = format_code_search(args, match_lines, files, &base)?;
+    fs::write(out_path, text).with_context(|| format!("writing {}", out_path))?;
+    println!("Saved {} result(s) to {}", match_lines.len(), out_path);
+    Ok(())
+}
+
+fn parse_code_search(content: &str) -> Result<Vec<Location>> {
+    // Results are "  12: text", context lines are "  12  text"
+    let line_re = Regex::new(r"^\s+(\d+)(:| )")?;
+    let mut locations = Vec::new();
+    let mut seen = HashSet::new();
+    let mut path: Option<&str> = None;
+
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(cap) = line_re.captures(line) {
+            if let Some(current) = path
+                && &cap[2] == ":"
+            {
+                let lineno: usize = cap[1].parse().context("parsing line number")?;
+                if seen.insert((current, lineno)) {
+                    locations.push(Location {
+                        path: current.to_string(),
+                        lines: LineSpan::Range(lineno, lineno),
+                        message: None,
+                    });
+                }
+            }
+        } else if !line.starts_with(char::is_whitespace)
+            && let Some(header) = line.strip_suffix(':')
+        {
+            path = Some(header);
+        }
+    }
+    Ok(locations)
+}
+
+fn format_code_search(
+    args: &Args,
+    match_lines: &[MatchLine],
+    files: &BTreeMap<FileAlias, FileInfo>,
+    base: &Utf8PathBuf,
+) -> Result<String> {
+    // VS Code can't search for case variants itself, so save the regex they
+    // were searched with
+    let query = match args.case_variants {
+        Some(ref word) => case_variants::search_pattern(&case_variants::variants(word)),
+        None => args
+            .pattern
+            .as_deref()
+            .or(args.fuzzy.as_deref())
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let is_regex = args.case_variants.is_some() || args.pattern.is_some();
+
+    let mut flags = Vec::new();
+    if is_regex && !args.fixed_strings {
+        flags.push("RegExp");
+    }
+    if !case_insensitive(&query, args) {
+        flags.push("CaseSensitive");
+    }
+    if args.word_regexp {
+        flags.push("WordMatch");
+    }
+
+    let mut out = String::new();
+    let escaped = query.replace('\\', "\\\\").replace('\n', "\\n");
+    writeln!(out, "# Query: {}", escaped)?;
+    if !flags.is_empty() {
+        writeln!(out, "# Flags: {}", flags.join(" "))?;
+    }
+    if !args.paths.is_empty() {
+        writeln!(out, "# Including: {}", args.paths.iter().join(", "))?;
+    }
+
+    // Lists may be in any order, and each file should only get one header
+    let sorted: Vec<&MatchLine> = match_lines
+        .iter()
+        .sorted_by_key(|m| (m.alias, m.lineno))
+        .collect();
+    let chunks = sorted.into_iter().chunk_by(|m| m.alias);
+    let file_count = match_lines.iter().map(|m| m.alias).unique().count();
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} result{} - {} file{}",
+        match_lines.len(),
+        if match_lines.len() == 1 { "" } else { "s" },
+        file_count,
+        if file_count == 1 { "" } else { "s" },
+    )?;
+
+    for (alias, lines) in &chunks {
+        let full_path = &files[&alias].full_path;
+        let path = relative_path(full_path, base);
+        let lines: Vec<&MatchLine> = lines.collect();
+        let width = lines.iter().map(|m| m.lineno.to_string().len()).max();
+
+        writeln!(out)?;
+        writeln!(out, "{}:", path)?;
+        for m in lines {
+            writeln!(
+                out,
+                "  {:>width$}: {}",
+                m.lineno,
+                m.original_content,
+                width = width.unwrap_or(1)
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_code_search() {
+        let content = "\
+# Query: fetch\\(
+# Flags: RegExp CaseSensitive
+# ContextLines: 1
+
+3 results - 2 files
+
+src/api.ts:
+   9    const url = base + path;
+  10:   return fetch(url);
+  11  }
+
+src/app.ts:
+  4: fetch(a); fetch(b);
+  4: fetch(a); fetch(b);
+  5:
+";
+        let locations = parse_code_search(content).unwrap();
+        let found: Vec<(&str, LineSpan)> = locations
+            .iter()
+            .map(|l| (l.path.as_str(), l.lines))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("src/api.ts", LineSpan::Range(10, 10)),
+                ("src/app.ts", LineSpan::Range(4, 4)),
+                ("src/app.ts", LineSpan::Range(5, 5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let args = Args::parse_from(["okapi", "-i", "fetch", "src"]);
+        let base = Utf8PathBuf::from("/work");
+        let (a, b) = (FileAlias::new(&['A']), FileAlias::new(&['B']));
+        let files = test_files(&[("/work/src/api.ts", ""), ("/work/src/app.ts", "")]);
+        // Out of order, as a --file list may be
+        let lines = vec![
+            MatchLine::new(a, 10, "  return fetch(url);".into()),
+            MatchLine::new(b, 2, "fetch(b)".into()),
+            MatchLine::new(a, 9, "fetch(a)".into()),
+        ];
+
+        let text = format_code_search(&args, &lines, &files, &base).unwrap();
+        assert_eq!(
+            text,
+            "# Query: fetch\n\
+             # Flags: RegExp\n\
+             # Including: src\n\
+             \n\
+             3 results - 2 files\n\
+             \n\
+             src/api.ts:\n\
+             \x20\x20\x209: fetch(a)\n\
+             \x20\x2010:   return fetch(url);\n\
+             \n\
+             src/app.ts:\n\
+             \x20\x202: fetch(b)\n"
+        );
+
+        let locations = parse_code_search(&text).unwrap();
+        assert_eq!(locations.len(), 3);
+        assert_eq!(locations[1].lines, LineSpan::Range(10, 10));
+
+        let args = Args::parse_from(["okapi", "--case-variants", "user_id"]);
+        let text = format_code_search(&args, &[], &files, &base).unwrap();
+        assert!(text.starts_with(
+            "# Query: \\\\b(?:user_id|USER_ID|user\\\\-id|userId|UserId)\\\\b\n\
+             # Flags: RegExp CaseSensitive\n"
+        ));
+    }
+}
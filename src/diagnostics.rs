@@ -0,0 +1,253 @@
+use crate::file_loader::{get_absolute_base, load_locations, read_input, resolve_path};
+use crate::locations::{LineSpan, Location};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+use log::debug;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// A machine-applicable rustc suggestion which replaces text within one line
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Suggestion {
+    path: String,
+    lineno: usize,
+    /// 1-indexed character columns, end exclusive
+    col_start: usize,
+    col_end: usize,
+    replacement: String,
+}
+
+/// Load the primary span of every diagnostic in `cargo --message-format=json`
+/// or `rustc --error-format=json` output. With --apply-suggestions, lines are
+/// pre-filled with rustc's machine-applicable fixes.
+pub fn load_from_diagnostics(
+    input: &Utf8PathBuf,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
+    let content = read_input(input)?;
+    let (locations, suggestions) = parse_diagnostics(&content)?;
+    let (mut matches, files) = load_locations(locations, args, filters)?;
+
+    if args.apply_suggestions {
+        let base = get_absolute_base(args)?;
+        let mut by_line: HashMap<(Utf8PathBuf, usize), Vec<Suggestion>> = HashMap::new();
+        for s in suggestions {
+            let key = (resolve_path(&base, &s.path), s.lineno);
+            by_line.entry(key).or_default().push(s);
+        }
+
+        for m in matches.iter_mut() {
+            let key = (files[&m.alias].full_path.clone(), m.lineno);
+            if let Some(suggestions) = by_line.get(&key) {
+                m.replacement = apply_suggestions(&m.original_content, suggestions);
+            }
+        }
+    }
+
+    let label = if input == "-" {
+        "Diagnostics: STDIN".to_string()
+    } else {
+        format!("Diagnostics: {}", input)
+    };
+    Ok((matches, files, label))
+}
+
+fn parse_diagnostics(content: &str) -> Result<(Vec<Location>, Vec<Suggestion>)> {
+    let mut locations = Vec::new();
+    let mut suggestions = Vec::new();
+    // Index of the location for each span, so that several diagnostics on one
+    // line share a buffer row. Cargo also repeats diagnostics when several
+    // targets share a file.
+    let mut seen: HashMap<(String, u64, u64), usize> = HashMap::new();
+
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            continue;
+        }
+        let value: Value = serde_json::from_str(line)
+            .with_context(|| format!("parsing JSON on line {}", idx + 1))?;
+
+        // Cargo wraps rustc's diagnostic; rustc on its own doesn't
+        let diag = match value["reason"].as_str() {
+            Some("compiler-message") => &value["message"],
+            Some(_) => continue,
+            None if value["$message_type"] == "diagnostic" => &value,
+            None => continue,
+        };
+
+        let Some(message) = describe(diag) else {
+            continue;
+        };
+        for span in primary_spans(diag) {
+            let (Some(path), Some(start), Some(end)) = (
+                span["file_name"].as_str(),
+                span["line_start"].as_u64(),
+                span["line_end"].as_u64(),
+            ) else {
+                continue;
+            };
+            match seen.get(&(path.to_string(), start, end)) {
+                Some(&i) => {
+                    let existing: &mut Location = &mut locations[i];
+                    let text = existing.message.get_or_insert_default();
+                    if !text.contains(&message) {
+                        text.push('\n');
+                        text.push_str(&message);
+                    }
+                }
+                None => {
+                    seen.insert((path.to_string(), start, end), locations.len());
+                    locations.push(Location {
+                        path: path.to_string(),
+                        lines: LineSpan::Range(start as usize, end as usize),
+                        message: Some(message.clone()),
+                    });
+                }
+            }
+        }
+
+        collect_suggestions(diag, &mut suggestions);
+    }
+
+    let suggestions = suggestions.into_iter().unique().collect();
+    Ok((locations, suggestions))
+}
+
+/// "warning[unused_mut]: variable does not need to be mutable", followed by
+/// any help from the diagnostic's children
+fn describe(diag: &Value) -> Option<String> {
+    let level = diag["level"].as_str()?;
+    let text = diag["message"].as_str()?;
+    let mut lines = vec![match diag["code"]["code"].as_str() {
+        Some(code) => format!("{}[{}]: {}", level, code, text),
+        None => format!("{}: {}", level, text),
+    }];
+
+    for child in diag["children"].as_array().into_iter().flatten() {
+        if child["level"] == "help"
+            && let Some(help) = child["message"].as_str()
+        {
+            lines.push(format!("help: {}", help));
+        }
+    }
+    Some(lines.join("\n"))
+}
+
+/// Spans marked primary, skipping those in macro definitions outside the
+/// source tree, like `<std macros>`
+fn primary_spans(diag: &Value) -> impl Iterator<Item = &Value> {
+    diag["spans"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|span| span["is_primary"] == true)
+        .filter(|span| {
+            let external = span["file_name"]
+                .as_str()
+                .is_none_or(|f| f.starts_with('<'));
+            if external {
+                debug!(
+                    "Skipping span outside the source tree: {}",
+                    span["file_name"]
+                );
+            }
+            !external
+        })
+}
+
+/// Gather machine-applicable replacements from a diagnostic and its children.
+/// Only those which stay within a single line can be shown in the buffer.
+fn collect_suggestions(diag: &Value, out: &mut Vec<Suggestion>) {
+    let spans = diag["spans"].as_array().into_iter().flatten();
+    for span in spans {
+        if span["suggestion_applicability"] != "MachineApplicable" {
+            continue;
+        }
+        let (Some(path), Some(start), Some(end), Some(col_start), Some(col_end), Some(text)) = (
+            span["file_name"].as_str(),
+            span["line_start"].as_u64(),
+            span["line_end"].as_u64(),
+            span["column_start"].as_u64(),
+            span["column_end"].as_u64(),
+            span["suggested_replacement"].as_str(),
+        ) else {
+            continue;
+        };
+        if start != end || text.contains('\n') {
+            debug!("Skipping multi-line suggestion in {}:{}", path, start);
+            continue;
+        }
+        out.push(Suggestion {
+            path: path.to_string(),
+            lineno: start as usize,
+            col_start: col_start as usize,
+            col_end: col_end as usize,
+            replacement: text.to_string(),
+        });
+    }
+
+    for child in diag["children"].as_array().into_iter().flatten() {
+        collect_suggestions(child, out);
+    }
+}
+
+/// Apply suggestions to a line, skipping any which overlap one already applied.
+/// Returns None if nothing changed.
+fn apply_suggestions(line: &str, suggestions: &[Suggestion]) -> Option<String> {
+    let mut chars: Vec<char> = line.chars().collect();
+    let mut applied: Vec<(usize, usize)> = Vec::new();
+
+    // Work right to left, so earlier columns stay valid
+    for s in suggestions
+        .iter()
+        .sorted_by_key(|s| std::cmp::Reverse(s.col_start))
+    {
+        let (start, end) = (s.col_start.saturating_sub(1), s.col_end.saturating_sub(1));
+        let overlaps = applied.iter().any(|&(a, b)| start < b && a < end);
+        if start > end || end > chars.len() || overlaps {
+            continue;
+        }
+        chars.splice(start..end, s.replacement.chars());
+        applied.push((start, end));
+    }
+
+    let result: String = chars.into_iter().collect();
+    (result != line).then_some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARGO_OUTPUT: &str = r#"{"reason":"compiler-artifact","package_id":"x"}
+{"reason":"compiler-message","message":{"$message_type":"diagnostic","level":"warning","message":"variable does not need to be mutable","code":{"code":"unused_mut"},"spans":[{"file_name":"src/main.rs","is_primary":true,"line_start":3,"line_end":3,"column_start":9,"column_end":14,"suggested_replacement":null,"suggestion_applicability":null}],"children":[{"level":"note","message":"on by default","spans":[],"children":[]},{"level":"help","message":"remove this `mut`","children":[],"spans":[{"file_name":"src/main.rs","is_primary":true,"line_start":3,"line_end":3,"column_start":9,"column_end":13,"suggested_replacement":"","suggestion_applicability":"MachineApplicable"}]}]}}
+{"$message_type":"diagnostic","level":"warning","message":"unused variable: `x`","code":{"code":"unused_variables"},"spans":[{"file_name":"src/main.rs","is_primary":true,"line_start":3,"line_end":3,"column_start":13,"column_end":14}],"children":[{"level":"help","message":"prefix it with an underscore","children":[],"spans":[{"file_name":"src/main.rs","is_primary":true,"line_start":3,"line_end":3,"column_start":13,"column_end":14,"suggested_replacement":"_x","suggestion_applicability":"MachineApplicable"}]}]}
+{"reason":"build-finished","success":true}"#;
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let (locations, suggestions) = parse_diagnostics(CARGO_OUTPUT).unwrap();
+
+        // Both diagnostics are on the same line, so they share a location
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].path, "src/main.rs");
+        assert_eq!(locations[0].lines, LineSpan::Range(3, 3));
+        assert_eq!(
+            locations[0].message.as_deref(),
+            Some(
+                "warning[unused_mut]: variable does not need to be mutable\n\
+                 help: remove this `mut`\n\
+                 warning[unused_variables]: unused variable: `x`\n\
+                 help: prefix it with an underscore"
+            )
+        );
+        assert_eq!(suggestions.len(), 2);
+
+        let fixed = apply_suggestions("    let mut x = 5;", &suggestions);
+        assert_eq!(fixed.as_deref(), Some("    let _x = 5;"));
+    }
+}
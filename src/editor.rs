@@ -1,3 +1,9 @@
+use crate::escape;
+use crate::file_loader::get_absolute_base;
+use crate::formats::{self, BufferFormat, Entry, Mark, Separator};
+use crate::normalize::{self, Form};
+use crate::prefix::{self, PrefixStyle};
+use crate::syntax;
 use crate::{Args, FileAlias, FileInfo, MatchLine};
 use anyhow::{Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
@@ -5,12 +11,56 @@ use camino_tempfile::tempdir;
 use crossterm::style::Stylize;
 use regex::Regex;
 use similar::{ChangeTag, TextDiff};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::process::{Command, ExitStatus};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Edits parsed from the buffer for one file, keyed by 1-indexed line number
+#[derive(Debug, Default)]
+struct FileChanges {
+    /// New content for a line, or `None` to delete it
+    lines: HashMap<usize, Option<String>>,
+    /// New lines to add above a line, in buffer order
+    above: BTreeMap<usize, Vec<String>>,
+    /// New lines to add below a line, in buffer order
+    below: BTreeMap<usize, Vec<String>>,
+    /// Lines moved away, which are also deletions in `lines`
+    moved_out: usize,
+    /// Lines moved in, which are also in `above` or `below`
+    moved_in: usize,
+    /// Other files which lines were moved to or from. They are written together
+    /// or not at all.
+    linked: BTreeSet<FileAlias>,
+}
+
+impl FileChanges {
+    /// The number of new lines to add to the file
+    fn inserted(&self) -> usize {
+        self.above
+            .values()
+            .chain(self.below.values())
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Put `content` at `lineno`, shifting the line there down. One past the
+    /// last line adds it to the end.
+    fn insert_at(&mut self, lineno: usize, line_count: usize, content: String) {
+        if lineno > line_count {
+            self.below.entry(line_count).or_default().push(content);
+        } else {
+            self.above.entry(lineno).or_default().push(content);
+        }
+    }
+}
+
+type Updates = HashMap<FileAlias, FileChanges>;
+
+/// A line which changed on disk since it was loaded: (lineno, original, user edit)
+type Conflict = (usize, String, String);
+
 pub fn run_editor_session(
     args: &Args,
     label: &str,
@@ -19,25 +69,82 @@ pub fn run_editor_session(
 ) -> Result<()> {
     let tmp_dir = tempdir().context("creating temporary directory")?;
     let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
-    let tmp_path: Utf8PathBuf = tmp_dir
-        .path()
-        .join(format!("edit-{}.okapi.txt", ts))
-        .try_into()?;
-
-    write_virtual_buffer(&tmp_path, label, &match_lines, &files)?;
+    // A raw buffer of one language gets its extension, so the editor highlights it
+    let ext = match args.format {
+        BufferFormat::Raw => syntax::session_language(&files).map_or("txt", |l| l.extensions[0]),
+        format => format.extension(),
+    };
+    let tmp_path: Utf8PathBuf = tmp_dir.path().join(format!("edit-{}.okapi.{}", ts, ext));
+    let index_path: Utf8PathBuf = tmp_dir.path().join(format!("edit-{}.okapi.index", ts));
+
+    let base = get_absolute_base(args)?;
+    // Tables get a real path column, whatever the line prefix style
+    let style = match args.format {
+        BufferFormat::Text => args.prefix.unwrap_or_default(),
+        BufferFormat::Csv | BufferFormat::Jsonl | BufferFormat::Raw => PrefixStyle::Path,
+    };
+    let prefixes = prefix::assign(&files, style, &base);
+    match args.format {
+        BufferFormat::Text => {
+            write_virtual_buffer(&tmp_path, label, &match_lines, &files, &prefixes, args)?
+        }
+        BufferFormat::Csv => formats::write_csv(&tmp_path, &match_lines, &prefixes)?,
+        BufferFormat::Jsonl => formats::write_jsonl(&tmp_path, &match_lines, &prefixes)?,
+        BufferFormat::Raw => formats::write_raw(&tmp_path, &index_path, &match_lines, &prefixes)?,
+    }
+    // Vim is given a syntax script which highlights the buffer in its language
+    let companion = match args.format {
+        BufferFormat::Text if syntax::session_language(&files).is_some() => {
+            let path = tmp_dir.path().join(format!("edit-{}.okapi.vim", ts));
+            fs::write(&path, syntax::companion(args.separator))?;
+            Some(path)
+        }
+        _ => None,
+    };
     let original_text = fs::read_to_string(&tmp_path)?;
+    let prefilled = match_lines.iter().any(|m| m.replacement.is_some());
 
-    let status = launch_editor(args, &tmp_path)?;
+    let status = launch_editor(args, &tmp_path, companion.as_deref())?;
 
     let new_text = fs::read_to_string(&tmp_path)?;
-    if new_text == original_text {
+    // Pre-filled suggestions are changes in their own right, even if the user saved nothing
+    if new_text == original_text && !prefilled {
         println!("No changes saved. Exiting.");
         return Ok(());
     }
 
     // 1. Parse the changes into memory first
-    let (updates, total_lines) = parse_changes(&new_text, &files)?;
-    let change_count = updates.values().map(|m| m.len()).sum::<usize>();
+    let entries = match args.format {
+        BufferFormat::Text => parse_text(&new_text, args.separator),
+        BufferFormat::Csv => formats::parse_csv(&new_text),
+        BufferFormat::Jsonl => formats::parse_jsonl(&new_text),
+        BufferFormat::Raw => fs::read_to_string(&index_path)
+            .map_err(Into::into)
+            .and_then(|index| formats::parse_raw(&new_text, &index)),
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => {
+            // Keep the user's work, since nothing was applied
+            let saved = save_abandoned(&tmp_path, ts, ext)?;
+            eprintln!(
+                "No files were changed. The buffer was saved to: {}",
+                saved.display()
+            );
+            if args.format == BufferFormat::Raw {
+                let index = save_abandoned(&index_path, ts, "index")?;
+                eprintln!("Its index was saved to: {}", index.display());
+            }
+            return Err(e);
+        }
+    };
+    let loaded: HashSet<(FileAlias, usize)> =
+        match_lines.iter().map(|m| (m.alias, m.lineno)).collect();
+    let (updates, total_lines) = parse_changes(entries, &files, &prefixes, &loaded, args);
+    let change_count = updates
+        .values()
+        .map(|c| c.lines.len() + c.inserted())
+        .sum::<usize>();
 
     if change_count == 0 {
         println!("No functional changes detected. Exiting.");
@@ -59,31 +166,52 @@ pub fn run_editor_session(
     if should_persist {
         perform_file_updates(updates, &files, total_lines)
     } else {
-        // Move buffer file so it isn't deleted when tmp_dir drops
-        let abandoned_path = std::env::temp_dir().join(format!("okapi-abandoned-{}.txt", ts));
-        fs::copy(&tmp_path, &abandoned_path)?;
+        let abandoned_path = save_abandoned(&tmp_path, ts, ext)?;
 
         println!("\nChanges abandoned.");
         println!(
             "The virtual buffer was saved to: {}",
             abandoned_path.display()
         );
+        if args.format == BufferFormat::Raw {
+            let index = save_abandoned(&index_path, ts, "index")?;
+            println!("Its index was saved to: {}", index.display());
+        }
         Ok(())
     }
 }
 
-fn prompt_user(msg: String) -> Result<bool> {
+/// Copy the buffer, or its raw index, out of the temporary directory, so it
+/// isn't deleted when tmp_dir drops
+fn save_abandoned(tmp_path: &Utf8Path, ts: u128, ext: &str) -> Result<std::path::PathBuf> {
+    let abandoned_path = std::env::temp_dir().join(format!("okapi-abandoned-{}.{}", ts, ext));
+    fs::copy(tmp_path, &abandoned_path)?;
+    Ok(abandoned_path)
+}
+
+pub fn prompt_user(msg: String) -> Result<bool> {
     print!("\n{} [y/N]: ", msg);
     io::stdout().flush()?;
 
     let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
+    // When the list was piped in, stdin is used up, so ask the terminal instead
+    match fs::File::open("/dev/tty") {
+        Ok(tty) if !io::stdin().is_terminal() => {
+            io::BufReader::new(tty).read_line(&mut input)?;
+        }
+        _ => {
+            io::stdin().read_line(&mut input)?;
+        }
+    }
     let input = input.trim().to_lowercase();
 
     Ok(input == "y" || input == "yes")
 }
 
-fn launch_editor(args: &Args, path: &Utf8Path) -> Result<ExitStatus> {
+/// Editors which can source a syntax script given with -S
+const VIM_EDITORS: [&str; 4] = ["vim", "nvim", "gvim", "mvim"];
+
+fn launch_editor(args: &Args, path: &Utf8Path, companion: Option<&Utf8Path>) -> Result<ExitStatus> {
     let editor_cmd = args
         .editor
         .clone()
@@ -92,7 +220,13 @@ fn launch_editor(args: &Args, path: &Utf8Path) -> Result<ExitStatus> {
 
     let mut parts = editor_cmd.split_whitespace();
     let cmd = parts.next().context("empty editor command")?;
-    let args_vec: Vec<_> = parts.chain(std::iter::once(path.as_ref())).collect();
+    let mut args_vec: Vec<&str> = parts.collect();
+    if let Some(script) = companion
+        && VIM_EDITORS.contains(&Utf8Path::new(cmd).file_name().unwrap_or(cmd))
+    {
+        args_vec.extend(["-S", script.as_str()]);
+    }
+    args_vec.push(path.as_str());
 
     let status = Command::new(cmd)
         .args(&args_vec)
@@ -104,43 +238,295 @@ fn launch_editor(args: &Args, path: &Utf8Path) -> Result<ExitStatus> {
 
 // Split the old apply_changes into two: parse and perform
 fn parse_changes(
-    new_text: &str,
+    entries: Vec<Entry>,
     files: &BTreeMap<FileAlias, FileInfo>,
-) -> Result<(HashMap<FileAlias, HashMap<usize, Option<String>>>, usize)> {
-    let line_re = Regex::new(r"^\s*([A-Z]+)\s+(\d+)\s+[▓░]\s?(.*)$")?;
-    let mut updates: HashMap<FileAlias, HashMap<usize, Option<String>>> = HashMap::new();
-    let mut total_lines = 0;
-
-    for line in new_text
-        .lines()
-        .filter(|l| !l.starts_with('#') && !l.trim().is_empty())
+    prefixes: &BTreeMap<FileAlias, String>,
+    loaded: &HashSet<(FileAlias, usize)>,
+    args: &Args,
+) -> (Updates, usize) {
+    let aliases: HashMap<&str, FileAlias> =
+        prefixes.iter().map(|(a, p)| (p.as_str(), *a)).collect();
+    let mut updates: Updates = HashMap::new();
+    let total_lines = entries
+        .iter()
+        .filter(|e| matches!(e.mark, Mark::Edit | Mark::Delete | Mark::MoveTo { .. }))
+        .count();
+    let keep_form = args.normalize || args.ignore_diacritics;
+    let mut forms: HashMap<FileAlias, Form> = HashMap::new();
+    let mut moved: HashSet<(FileAlias, usize)> = HashSet::new();
+    let sep = args.separator.chars();
+    let row_re = (args.format == BufferFormat::Text).then(|| row_pattern(args.separator));
+    let joined = |content: &str| {
+        row_re
+            .as_ref()
+            .is_some_and(|re| holds_row(content, re, &aliases))
+    };
+    // Without --escape, the text and raw buffers don't show a '\r' ending a
+    // line, so it's put back: lines keep their own, and new lines take their
+    // file's
+    let hides_cr = !args.escape && matches!(args.format, BufferFormat::Text | BufferFormat::Raw);
+    let forced_by = if args.escape {
+        "write the separator after its line number as an escape"
+    } else {
+        "edit it again with --escape and write the separator after its line number as an escape"
+    };
+
+    for Entry {
+        prefix,
+        lineno,
+        mark,
+        content: new_content,
+    } in entries
     {
-        if line.chars().filter(|&c| c == '▓' || c == '░').count() > 1 {
+        let Some(&alias) = aliases.get(prefix.as_str()) else {
+            continue;
+        };
+        // Checked as typed, so that an escaped separator or '\r' doesn't count
+        let typed_row = joined(&new_content);
+        let typed_breaks = line_breaks(&new_content);
+        let new_content = if args.escape && mark != Mark::Delete {
+            match escape::decode(&new_content, &sep) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    eprintln!("Skipping {} line {}: {}", prefix, lineno, e);
+                    continue;
+                }
+            }
+        } else {
+            new_content
+        };
+        // A row whose prefix or line number was changed would otherwise
+        // overwrite whatever line is there now
+        let replaces_line = matches!(mark, Mark::Edit | Mark::Delete | Mark::MoveTo { .. });
+        if replaces_line && !loaded.contains(&(alias, lineno)) {
+            eprintln!(
+                "Skipping {} line {}: it wasn't loaded into the buffer. To move a line \
+                 there, write '> {} {}' before its separator.",
+                prefix, lineno, prefix, lineno
+            );
             continue;
         }
 
-        if let Some(cap) = line_re.captures(line) {
-            total_lines += 1;
-            let alias = FileAlias::from_str(cap.get(1).unwrap().as_str());
-            let lineno: usize = cap.get(2).unwrap().as_str().parse()?;
-            let new_content = cap.get(3).unwrap().as_str();
-
-            if let Some(file) = files.get(&alias) {
-                let orig_lines: Vec<&str> = file.original_content.lines().collect();
-                if let Some(&orig) = orig_lines.get(lineno - 1) {
-                    if new_content.trim().is_empty() {
-                        updates.entry(alias).or_default().insert(lineno, None);
-                    } else if orig != new_content {
+        if let Some(file) = files.get(&alias) {
+            let orig_lines: Vec<&str> = file.original_content.split_terminator('\n').collect();
+            if let Some(&orig) = lineno.checked_sub(1).and_then(|i| orig_lines.get(i)) {
+                // A new line has no original text to keep
+                let orig = if replaces_line { orig } else { "" };
+                // CSV and JSON cells can hold line breaks, but a line can't gain
+                // one. Those already in the line, like a stray '\r', are kept.
+                if typed_breaks > line_breaks(orig) && new_content != orig {
+                    eprintln!(
+                        "Skipping {} line {}: a line can't contain a line break",
+                        prefix, lineno
+                    );
+                    continue;
+                }
+                // Keep the file's normalization form, whatever the editor saved
+                let new_content = if keep_form {
+                    let form = *forms
+                        .entry(alias)
+                        .or_insert_with(|| Form::detect(&file.original_content));
+                    normalize::restore_form(orig, &new_content, form)
+                } else {
+                    new_content
+                };
+                if typed_row && !joined(orig) {
+                    eprintln!(
+                        "Skipping {} line {}: it holds another row, as if two rows were joined. \
+                         To keep it, {}, like \\{}.",
+                        prefix, lineno, forced_by, args.separator.heavy
+                    );
+                    continue;
+                }
+
+                let new_content = match mark {
+                    _ if !hides_cr => new_content,
+                    Mark::Edit => with_cr(new_content, orig),
+                    _ => with_cr(new_content, first_line(&file.original_content)),
+                };
+
+                let edited = mark == Mark::Delete || orig != new_content;
+                match mark {
+                    Mark::Edit | Mark::Delete if edited && moved.contains(&(alias, lineno)) => {
+                        eprintln!(
+                            "Skipping {} line {}: a moved line can't also be edited in place",
+                            prefix, lineno
+                        );
+                    }
+                    Mark::Delete => {
+                        updates.entry(alias).or_default().lines.insert(lineno, None);
+                    }
+                    Mark::Edit if edited => {
                         updates
                             .entry(alias)
                             .or_default()
-                            .insert(lineno, Some(new_content.to_string()));
+                            .lines
+                            .insert(lineno, Some(new_content));
+                    }
+                    Mark::Edit => {}
+                    Mark::InsertAbove => {
+                        let changes = updates.entry(alias).or_default();
+                        changes.above.entry(lineno).or_default().push(new_content);
+                    }
+                    Mark::InsertBelow => {
+                        let changes = updates.entry(alias).or_default();
+                        changes.below.entry(lineno).or_default().push(new_content);
+                    }
+                    Mark::MoveTo {
+                        prefix: to_prefix,
+                        lineno: to,
+                    } => {
+                        let to_alias = match to_prefix.as_deref() {
+                            Some(p) => aliases.get(p).copied(),
+                            None => Some(alias),
+                        };
+                        let Some(to_alias) = to_alias else {
+                            eprintln!(
+                                "Skipping {} line {}: no file has the prefix {}",
+                                prefix,
+                                lineno,
+                                to_prefix.unwrap_or_default()
+                            );
+                            continue;
+                        };
+                        let to_file = &files[&to_alias];
+                        let line_count = to_file.original_content.lines().count();
+                        if to == 0 || to > line_count + 1 {
+                            eprintln!(
+                                "Skipping {} line {}: {} has no line {} to move it to",
+                                prefix, lineno, to_file.path, to
+                            );
+                            continue;
+                        }
+                        if updates
+                            .get(&alias)
+                            .is_some_and(|c| c.lines.contains_key(&lineno))
+                        {
+                            eprintln!(
+                                "Skipping {} line {}: a moved line can't also be edited in place",
+                                prefix, lineno
+                            );
+                            continue;
+                        }
+
+                        moved.insert((alias, lineno));
+                        let from = updates.entry(alias).or_default();
+                        from.lines.insert(lineno, None);
+                        from.moved_out += 1;
+                        if to_alias != alias {
+                            from.linked.insert(to_alias);
+                        }
+                        let new_content = if hides_cr && to_alias != alias {
+                            let stripped = new_content.strip_suffix('\r').unwrap_or(&new_content);
+                            with_cr(stripped.to_string(), first_line(&to_file.original_content))
+                        } else {
+                            new_content
+                        };
+                        let dest = updates.entry(to_alias).or_default();
+                        dest.insert_at(to, line_count, new_content);
+                        dest.moved_in += 1;
+                        if to_alias != alias {
+                            dest.linked.insert(alias);
+                        }
                     }
                 }
             }
         }
     }
-    Ok((updates, total_lines))
+    (updates, total_lines)
+}
+
+/// Matches the line number and separator of a row, after its prefix
+fn row_pattern(separator: Separator) -> Regex {
+    Regex::new(&format!(r"\s+\d+\s+{}(?:\s|$)", separator.marker_class()))
+        .expect("row pattern is valid")
+}
+
+/// End `content` with a '\r' if `like` ends with one
+fn with_cr(mut content: String, like: &str) -> String {
+    if like.ends_with('\r') && !content.ends_with('\r') {
+        content.push('\r');
+    }
+    content
+}
+
+fn first_line(content: &str) -> &str {
+    content.split_terminator('\n').next().unwrap_or_default()
+}
+
+fn line_breaks(content: &str) -> usize {
+    content.matches(['\n', '\r']).count()
+}
+
+/// Whether `content` holds what looks like a whole row, which is what's left
+/// when two rows are joined into one
+fn holds_row(content: &str, row_re: &Regex, prefixes: &HashMap<&str, FileAlias>) -> bool {
+    row_re.find_iter(content).any(|m| {
+        let before = &content[..m.start()];
+        prefixes.keys().any(|p| {
+            before
+                .strip_suffix(p)
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with(char::is_whitespace))
+        })
+    })
+}
+
+/// Read the editable lines of the text buffer. Each row's separator is the
+/// first after its line number, so content may contain separators too. A row
+/// marked '+' instead of a separator is a new line, added above its line if it
+/// comes before that line's row, and below it otherwise. A row marked '-' is
+/// deleted. A row with '> B 7' before its separator is moved to line 7 of file
+/// B, or of its own file if only the line is given.
+fn parse_text(new_text: &str, separator: Separator) -> Result<Vec<Entry>> {
+    // The prefixes are matched lazily, so a path with spaces in it still works
+    let line_re = Regex::new(&format!(
+        r"^\s*(\S.*?)\s+(\d+)\s+(?:>\s*(?:(\S.*?)\s+)?(\d+)\s+)?({})\s?(.*)$",
+        separator.marker_class()
+    ))?;
+    let mut entries = Vec::new();
+
+    for line in new_text
+        .lines()
+        .filter(|l| !l.starts_with('#') && !l.trim().is_empty())
+    {
+        if let Some(cap) = line_re.captures(line) {
+            let lineno: usize = cap[2].parse()?;
+            let mut entry = Entry::new(&cap[1], lineno, &cap[6]);
+            if let Some(to) = cap.get(4) {
+                entry.mark = Mark::MoveTo {
+                    prefix: cap.get(3).map(|p| p.as_str().to_string()),
+                    lineno: to.as_str().parse()?,
+                };
+            } else if &cap[5] == "+" {
+                entry.mark = Mark::InsertBelow;
+            } else if &cap[5] == "-" {
+                entry.mark = Mark::Delete;
+            }
+            entries.push(entry);
+        }
+    }
+
+    let rows: HashMap<(&str, usize), usize> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.mark == Mark::Edit)
+        .map(|(i, e)| ((e.prefix.as_str(), e.lineno), i))
+        .collect();
+    let above: Vec<usize> = entries
+        .iter()
+        .enumerate()
+        .filter(|(i, e)| {
+            e.mark == Mark::InsertBelow
+                && rows
+                    .get(&(e.prefix.as_str(), e.lineno))
+                    .is_some_and(|row| row > i)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    for i in above {
+        entries[i].mark = Mark::InsertAbove;
+    }
+    Ok(entries)
 }
 
 fn write_virtual_buffer(
@@ -148,18 +534,46 @@ fn write_virtual_buffer(
     label: &str,
     match_lines: &[MatchLine],
     files: &BTreeMap<FileAlias, FileInfo>,
+    prefixes: &BTreeMap<FileAlias, String>,
+    args: &Args,
 ) -> Result<()> {
     let mut file = fs::File::create(tmp)?;
-    writeln!(file, "# okapi – bulk editing buffer\n# {}\n#", label)?;
+    writeln!(file, "# okapi – bulk editing buffer\n# {}", label)?;
+    // Read by the definitions from --syntax, to highlight the content
+    if let Some(language) = syntax::session_language(files) {
+        writeln!(file, "# Language: {}", language.name)?;
+    }
+    writeln!(file, "#")?;
     writeln!(file, "# - Save and close to apply changes.")?;
     writeln!(
         file,
         "# - Unchanged lines and those starting with '#' are ignored."
     )?;
+    let sep = args.separator.heavy;
     writeln!(
         file,
-        "# - Delete everything after the shade block (▓) to remove a line.\n#"
+        "# - To delete a line, change the {} after its line number to '-'. Blank lines are kept.",
+        sep
     )?;
+    writeln!(
+        file,
+        "# - To add a line, copy a line above or below itself and change its {} to '+'.",
+        sep
+    )?;
+    writeln!(
+        file,
+        "# - To move a line to line 7 of file B, write '> B 7' before its {}.",
+        sep
+    )?;
+    if args.escape {
+        writeln!(
+            file,
+            "# - Escapes: \\t tab, \\r carriage return, \\s trailing space, \\u{{200B}} other invisible\n\
+             #   characters, \\\\ for a backslash, and \\{} for a {}.",
+            sep, sep
+        )?;
+    }
+    writeln!(file, "#")?;
     writeln!(file, "# --- Begin editable lines ---\n")?;
 
     let max_w = match_lines
@@ -167,6 +581,13 @@ fn write_virtual_buffer(
         .map(|m| (m.lineno as f64).log10() as usize + 1)
         .max()
         .unwrap_or(1);
+    // Letter aliases only grow past three in sessions of over 18,278 files
+    let prefix_w = prefixes
+        .values()
+        .map(|p| p.chars().count())
+        .max()
+        .unwrap_or_default()
+        .max(3);
     let mut current_alias = None;
     let mut use_heavy = false;
 
@@ -175,81 +596,141 @@ fn write_virtual_buffer(
             current_alias = Some(m.alias);
             use_heavy = !use_heavy;
         }
-        let pipe = if use_heavy { "▓" } else { "░" };
+        let pipe = if use_heavy {
+            args.separator.heavy
+        } else {
+            args.separator.light
+        };
+        for note in m.annotation.iter().flat_map(|n| n.lines()) {
+            // Align the note with the content column below it
+            writeln!(file, "#{:pad$}{}", "", note, pad = prefix_w + max_w + 3)?;
+        }
+        let prefix = &prefixes[&m.alias];
+        let prefix = if args.prefix.unwrap_or_default().right_aligned() {
+            format!("{:>prefix_w$}", prefix)
+        } else {
+            format!("{:<prefix_w$}", prefix)
+        };
+        let content = m.replacement.as_ref().unwrap_or(&m.original_content);
+        let content = if args.escape {
+            escape::encode(content, &args.separator.chars())
+        } else {
+            content.strip_suffix('\r').unwrap_or(content).to_string()
+        };
         writeln!(
             file,
-            "{:>3} {:>width$} {} {}",
-            m.alias,
+            "{} {:>width$} {} {}",
+            prefix,
             m.lineno,
             pipe,
-            m.original_content,
+            content,
             width = max_w
         )?;
     }
 
     writeln!(file, "\n# --- File Aliases ---")?;
-    for (_, f) in files {
-        writeln!(file, "# {:>3} = {}", f.alias, f.full_path)?;
+    for f in files.values() {
+        writeln!(
+            file,
+            "# {:>prefix_w$} = {}",
+            prefixes[&f.alias], f.full_path
+        )?;
     }
     Ok(())
 }
 
 fn perform_file_updates(
-    updates: HashMap<FileAlias, HashMap<usize, Option<String>>>,
+    updates: Updates,
     files: &BTreeMap<FileAlias, FileInfo>,
     all_lines: usize,
 ) -> Result<()> {
-    let (mut line_count, mut file_count) = (0, 0);
-
-    for (alias, changes) in updates {
+    // Resolve every file before writing any, so that a line moved between
+    // files is never removed from one without being added to the other
+    let mut resolved = BTreeMap::new();
+    let mut blocked = Vec::new();
+    for (&alias, changes) in &updates {
         let f = files.get(&alias).context("missing file alias")?;
         let on_disk = fs::read_to_string(&f.full_path)?;
 
-        match resolve_file_changes(&on_disk, &f.original_content, &changes) {
+        match resolve_file_changes(&on_disk, &f.original_content, changes) {
             Err(conflicts) => {
                 eprintln!("Conflict in {}: modified externally", f.path);
                 for (i, o, n) in conflicts {
                     print_diff(i, &o, &n);
                 }
+                blocked.push(alias);
             }
-            Ok((new_text, affected)) => {
-                if let Some(txt) = new_text {
-                    fs::write(&f.full_path, txt)?;
-                    println!("Updated {}", f.path);
-                } else if affected > 0 {
-                    println!("Verified {} (already up to date)", f.path);
-                }
-                line_count += affected;
-                file_count += 1;
+            Ok(result) => {
+                resolved.insert(alias, result);
+            }
+        }
+    }
+    while let Some(alias) = blocked.pop() {
+        for linked in &updates[&alias].linked {
+            if resolved.remove(linked).is_some() {
+                eprintln!(
+                    "Skipping {}: lines were moved between it and {}, which has a conflict",
+                    files[linked].path, files[&alias].path
+                );
+                blocked.push(*linked);
             }
         }
     }
 
-    print_summary(line_count, file_count, all_lines, files.len());
+    let (mut line_count, mut file_count, mut added, mut moved) = (0, 0, 0, 0);
+    for (alias, (new_text, affected)) in resolved {
+        let f = &files[&alias];
+        let changes = &updates[&alias];
+        if let Some(txt) = new_text {
+            fs::write(&f.full_path, txt)?;
+            println!("Updated {}", f.path);
+        } else if affected > 0 {
+            println!("Verified {} (already up to date)", f.path);
+        }
+        line_count += affected.saturating_sub(changes.moved_out);
+        added += changes.inserted() - changes.moved_in;
+        moved += changes.moved_in;
+        file_count += 1;
+    }
+
+    print_summary(line_count, file_count, added, moved, all_lines, files.len());
     Ok(())
 }
 
 fn resolve_file_changes(
     on_disk: &str,
     original: &str,
-    changes: &HashMap<usize, Option<String>>,
-) -> Result<(Option<String>, usize), Vec<(usize, String, String)>> {
+    changes: &FileChanges,
+) -> Result<(Option<String>, usize), Vec<Conflict>> {
     let mut conflicts = Vec::new();
     let mut modified = false;
-    let disk_lines: Vec<&str> = on_disk.lines().collect();
-    let orig_lines: Vec<&str> = original.lines().collect();
+    // Lines keep their '\r', so joining them again keeps CRLF line endings
+    let disk_lines: Vec<&str> = on_disk.split_terminator('\n').collect();
+    let orig_lines: Vec<&str> = original.split_terminator('\n').collect();
 
-    for (&idx, user_val) in changes {
+    for (&idx, user_val) in &changes.lines {
         let disk = disk_lines.get(idx - 1).copied().unwrap_or("");
         let orig = orig_lines.get(idx - 1).copied().unwrap_or("");
-        let user = user_val.as_deref().unwrap_or("");
 
-        if disk != user {
-            if disk == orig {
-                modified = true;
-            } else {
-                conflicts.push((idx, orig.to_string(), user.to_string()));
-            }
+        match user_val.as_deref() {
+            // Already made on disk. A deletion can't be told apart from a
+            // blank line, so it always has to find the original.
+            Some(user) if disk == user => {}
+            _ if disk == orig => modified = true,
+            user => conflicts.push((idx, orig.to_string(), user.unwrap_or("").to_string())),
+        }
+    }
+
+    // New lines are placed next to a line, which must still be where it was
+    for (&idx, new_lines) in changes.above.iter().chain(&changes.below) {
+        let disk = disk_lines.get(idx - 1).copied().unwrap_or("");
+        let orig = orig_lines.get(idx - 1).copied().unwrap_or("");
+        let edited = changes.lines.get(&idx).map(|v| v.as_deref().unwrap_or(""));
+
+        if disk == orig || Some(disk) == edited {
+            modified = true;
+        } else {
+            conflicts.extend(new_lines.iter().map(|l| (idx, String::new(), l.clone())));
         }
     }
 
@@ -257,28 +738,41 @@ fn resolve_file_changes(
         return Err(conflicts);
     }
     if !modified {
-        return Ok((None, changes.len()));
+        return Ok((None, changes.lines.len()));
     }
 
-    let mut idx = 0;
-    let mut final_lines: Vec<String> = disk_lines.iter().map(|s| s.to_string()).collect();
-    final_lines.retain_mut(|line| {
-        idx += 1;
-        match changes.get(&idx) {
-            Some(Some(new_val)) => {
-                *line = new_val.clone();
-                true
-            }
-            Some(None) => false,
-            None => true,
+    // Build the file in one pass, so new lines don't shift the numbers of later ones
+    let mut final_lines: Vec<&str> = Vec::with_capacity(disk_lines.len() + changes.inserted());
+    for (i, &line) in disk_lines.iter().enumerate() {
+        let idx = i + 1;
+        final_lines.extend(
+            changes
+                .above
+                .get(&idx)
+                .into_iter()
+                .flatten()
+                .map(String::as_str),
+        );
+        match changes.lines.get(&idx) {
+            Some(Some(new_val)) => final_lines.push(new_val),
+            Some(None) => {}
+            None => final_lines.push(line),
         }
-    });
+        final_lines.extend(
+            changes
+                .below
+                .get(&idx)
+                .into_iter()
+                .flatten()
+                .map(String::as_str),
+        );
+    }
 
     let mut output = final_lines.join("\n");
     if original.ends_with('\n') {
         output.push('\n');
     }
-    Ok((Some(output), changes.len()))
+    Ok((Some(output), changes.lines.len()))
 }
 
 fn print_diff(lineno: usize, original: &str, updated: &str) {
@@ -308,15 +802,219 @@ fn print_diff(lineno: usize, original: &str, updated: &str) {
     println!();
 }
 
-fn print_summary(lines_chg: usize, files_chg: usize, lines_total: usize, files_total: usize) {
-    let w = (lines_total as f64).log10().ceil() as usize;
+fn print_summary(
+    lines_chg: usize,
+    files_chg: usize,
+    lines_added: usize,
+    lines_moved: usize,
+    lines_total: usize,
+    files_total: usize,
+) {
+    let w = (lines_total.max(lines_added) as f64).log10().ceil() as usize;
     println!(
         "\n  Changed: {:>w$} line(s), {:>w$} file(s)",
         lines_chg, files_chg
     );
+    if lines_added > 0 {
+        println!("    Added: {:>w$} line(s)", lines_added);
+    }
+    if lines_moved > 0 {
+        println!("    Moved: {:>w$} line(s)", lines_moved);
+    }
     println!(
         "Unchanged: {:>w$} line(s), {:>w$} file(s)",
-        lines_total - lines_chg,
+        lines_total - lines_chg - lines_moved,
         files_total - files_chg
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files;
+    use clap::Parser;
+
+    fn shade() -> Separator {
+        formats::parse_separator("shade").unwrap()
+    }
+
+    #[test]
+    fn test_inserted_lines() {
+        let buffer = "# okapi – bulk editing buffer\n\
+                      A 2 + above\n\
+                      A 2 ▓ TWO\n\
+                      A 2 + below\n\
+                      A 4 + after four\n";
+        let marks: Vec<Mark> = parse_text(buffer, shade())
+            .unwrap()
+            .into_iter()
+            .map(|e| e.mark)
+            .collect();
+        assert_eq!(
+            marks,
+            [
+                Mark::InsertAbove,
+                Mark::Edit,
+                Mark::InsertBelow,
+                Mark::InsertBelow
+            ]
+        );
+
+        let mut changes = FileChanges::default();
+        changes.lines.insert(2, Some("TWO".into()));
+        changes.above.insert(2, vec!["above".into()]);
+        changes.below.insert(2, vec!["below".into()]);
+        changes.below.insert(4, vec!["after four".into()]);
+        let original = "one\ntwo\nthree\nfour\n";
+        let (text, affected) = resolve_file_changes(original, original, &changes).unwrap();
+        assert_eq!(
+            text.unwrap(),
+            "one\nabove\nTWO\nbelow\nthree\nfour\nafter four\n"
+        );
+        assert_eq!(affected, 1);
+
+        // The line a new one goes next to has changed on disk
+        let conflicts =
+            resolve_file_changes("one\ntwo\nthree\nFOUR\n", original, &changes).unwrap_err();
+        assert_eq!(conflicts, vec![(4, String::new(), "after four".into())]);
+    }
+
+    #[test]
+    fn test_moved_lines() {
+        let buffer = "  A 2 > B 10 ▓ to another file\n\
+                      src/a b.rs 4 > 1 ░ to the top\n\
+                      A 3 ▓ > not a move\n";
+        let marks: Vec<Mark> = parse_text(buffer, shade())
+            .unwrap()
+            .into_iter()
+            .map(|e| e.mark)
+            .collect();
+        assert_eq!(
+            marks,
+            [
+                Mark::MoveTo {
+                    prefix: Some("B".into()),
+                    lineno: 10
+                },
+                Mark::MoveTo {
+                    prefix: None,
+                    lineno: 1
+                },
+                Mark::Edit,
+            ]
+        );
+
+        // Line 3 moves to the top, and line 1 to the end
+        let mut changes = FileChanges::default();
+        changes.lines.insert(3, None);
+        changes.lines.insert(1, None);
+        changes.insert_at(1, 3, "three".into());
+        changes.insert_at(4, 3, "one".into());
+        let original = "one\ntwo\nthree\n";
+        let (text, _) = resolve_file_changes(original, original, &changes).unwrap();
+        assert_eq!(text.unwrap(), "three\ntwo\none\n");
+    }
+
+    #[test]
+    fn test_blank_and_deleted_lines() {
+        let buffer = "A 1 ▓ \n\
+                      A 2 ▓     \n\
+                      A 3 -  x\n";
+        let entries = parse_text(buffer, shade()).unwrap();
+        assert_eq!(entries[0], Entry::new("A", 1, ""));
+        assert_eq!(entries[1], Entry::new("A", 2, "    "));
+        assert_eq!(entries[2].mark, Mark::Delete);
+
+        // Blanking a line keeps it, and a blank line can be deleted
+        let mut changes = FileChanges::default();
+        changes.lines.insert(1, Some("".into()));
+        changes.lines.insert(2, Some("    ".into()));
+        changes.lines.insert(3, None);
+        let original = "def f():\n    pass\n\nx = 1\n";
+        let (text, _) = resolve_file_changes(original, original, &changes).unwrap();
+        assert_eq!(text.unwrap(), "\n    \nx = 1\n");
+    }
+
+    #[test]
+    fn test_separators_in_content() {
+        let entries = parse_text("  A 3 ▓ │▓▓░░│ 1 ░ x\n", shade()).unwrap();
+        assert_eq!(entries, [Entry::new("A", 3, "│▓▓░░│ 1 ░ x")]);
+
+        let ascii = formats::parse_separator("ascii").unwrap();
+        let entries = parse_text("A 3 | a || b\nB 9 : c: d\nA 4 - x\n", ascii).unwrap();
+        assert_eq!(entries[0], Entry::new("A", 3, "a || b"));
+        assert_eq!(entries[1], Entry::new("B", 9, "c: d"));
+        assert_eq!(entries[2].mark, Mark::Delete);
+
+        // Two rows joined into one
+        let aliases = HashMap::from([("A", FileAlias::from_index(0))]);
+        let row_re = row_pattern(shade());
+        assert!(holds_row("let a = 1; A 4 ▓ let b = 2;", &row_re, &aliases));
+        assert!(!holds_row("│▓▓░░│ 1 ░ x", &row_re, &aliases));
+        assert!(!holds_row("BA 4 ▓ x", &row_re, &aliases));
+    }
+
+    #[test]
+    fn test_line_breaks_in_cells() {
+        let args = Args::parse_from(["okapi", "--format", "csv", "x"]);
+        let files = test_files(&[("a.txt", "a\rb\nc\nd\n")]);
+        let alias = FileAlias::from_index(0);
+        let prefixes = BTreeMap::from([(alias, "a.txt".to_string())]);
+        let loaded = HashSet::from([(alias, 1), (alias, 2), (alias, 3)]);
+        let entries = vec![
+            // Left as it was, and edited around a '\r' it already had
+            Entry::new("a.txt", 1, "a\rb"),
+            Entry::new("a.txt", 1, "a\rB"),
+            // Given a new line break
+            Entry::new("a.txt", 2, "c\nC"),
+            Entry::new("a.txt", 3, "D"),
+        ];
+        let (updates, _) = parse_changes(entries, &files, &prefixes, &loaded, &args);
+        let lines = &updates[&alias].lines;
+        assert_eq!(lines.get(&1), Some(&Some("a\rB".to_string())));
+        assert_eq!(lines.get(&2), None);
+        assert_eq!(lines.get(&3), Some(&Some("D".to_string())));
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let files = test_files(&[("a.txt", "a\r\nb\r\nc\r\n")]);
+        let alias = FileAlias::from_index(0);
+        let prefixes = BTreeMap::from([(alias, "A".to_string())]);
+        let loaded = HashSet::from([(alias, 1), (alias, 2), (alias, 3)]);
+        let mut inserted = Entry::new("A", 2, "b2");
+        inserted.mark = Mark::InsertBelow;
+        let entries = || {
+            vec![
+                Entry::new("A", 1, "a"),
+                Entry::new("A", 2, "B"),
+                inserted.clone(),
+                Entry::new("A", 3, "c\\r"),
+            ]
+        };
+
+        // The buffer didn't show the '\r', so lines keep it
+        let args = Args::parse_from(["okapi", "x"]);
+        let (updates, _) = parse_changes(entries(), &files, &prefixes, &loaded, &args);
+        let changes = &updates[&alias];
+        assert_eq!(changes.lines.get(&1), None);
+        assert_eq!(changes.lines.get(&2), Some(&Some("B\r".to_string())));
+        assert_eq!(changes.below[&2], ["b2\r"]);
+        assert_eq!(changes.lines.get(&3), Some(&Some("c\\r\r".to_string())));
+
+        // With --escape it's edited like any other character
+        let args = Args::parse_from(["okapi", "--escape", "x"]);
+        let (updates, _) = parse_changes(entries(), &files, &prefixes, &loaded, &args);
+        let changes = &updates[&alias];
+        assert_eq!(changes.lines.get(&1), Some(&Some("a".to_string())));
+        assert_eq!(changes.below[&2], ["b2"]);
+        assert_eq!(changes.lines.get(&3), None);
+
+        // Lines which weren't edited keep their endings on disk
+        let original = &files[&alias].original_content;
+        let mut changes = FileChanges::default();
+        changes.lines.insert(2, Some("B\r".into()));
+        let (text, _) = resolve_file_changes(original, original, &changes).unwrap();
+        assert_eq!(text.unwrap(), "a\r\nB\r\nc\r\n");
+    }
+}
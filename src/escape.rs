@@ -0,0 +1,116 @@
+use anyhow::{Context, Result, bail};
+use std::fmt::Write;
+
+/// Characters which can't be seen, or are easily mistaken for a space: controls,
+/// zero-width and bidirectional formatting characters, and unusual spaces
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{A0}'
+                | '\u{AD}'
+                | '\u{180E}'
+                | '\u{2000}'..='\u{200F}'
+                | '\u{2028}'..='\u{202F}'
+                | '\u{205F}'..='\u{2069}'
+                | '\u{3000}'
+                | '\u{FEFF}'
+        )
+}
+
+/// Write `line` so that every character can be seen and survives an editor
+/// which strips trailing whitespace. Tabs and carriage returns become `\t` and
+/// `\r`, trailing spaces `\s`, other invisible characters `\u{200B}`, and
+/// backslashes are doubled. The buffer's `separator` characters get a
+/// backslash too, so only the row's own separator is left bare.
+pub fn encode(line: &str, separator: &[char]) -> String {
+    let trailing_from = line.trim_end().len();
+    let mut escaped = String::with_capacity(line.len());
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            ' ' if i >= trailing_from => escaped.push_str("\\s"),
+            c if separator.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if is_invisible(c) || i >= trailing_from => {
+                write!(escaped, "\\u{{{:X}}}", c as u32).expect("writing to a String")
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo `encode`. Any other backslash sequence is an error, rather than a guess
+/// at what was meant.
+pub fn decode(line: &str, separator: &[char]) -> Result<String> {
+    let mut decoded = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => decoded.push('\\'),
+            Some('t') => decoded.push('\t'),
+            Some('r') => decoded.push('\r'),
+            Some('s') => decoded.push(' '),
+            Some(c) if separator.contains(&c) => decoded.push(c),
+            Some('u') => {
+                let (hex, rest) = chars
+                    .as_str()
+                    .strip_prefix('{')
+                    .and_then(|r| r.split_once('}'))
+                    .context("\\u needs a code point in braces, like \\u{200B}")?;
+                let c = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .with_context(|| format!("\\u{{{}}} isn't a character", hex))?;
+                if c == '\n' {
+                    bail!("a line can't contain a line break");
+                }
+                decoded.push(c);
+                chars = rest.chars();
+            }
+            Some(other) => bail!("unknown escape \\{}. Write \\\\ for a backslash", other),
+            None => bail!("the line ends in a lone \\. Write \\\\ for a backslash"),
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let line = "\tlet re = \"\\\\d+\";\u{200B} // café\r  ";
+        let sep = ['▓', '░'];
+        let escaped = encode(line, &sep);
+        assert_eq!(
+            escaped,
+            "\\tlet re = \"\\\\\\\\d+\";\\u{200B} // café\\r\\s\\s"
+        );
+        assert_eq!(decode(&escaped, &sep).unwrap(), line);
+
+        assert_eq!(encode("    ", &sep), "\\s\\s\\s\\s");
+        assert_eq!(encode("a\u{A0}b\u{7}", &sep), "a\\u{A0}b\\u{7}");
+        assert_eq!(decode("a\\u{a0}b", &sep).unwrap(), "a\u{A0}b");
+        assert_eq!(encode("│▓░│", &sep), "│\\▓\\░│");
+        assert_eq!(decode("│\\▓\\░│", &sep).unwrap(), "│▓░│");
+
+        assert!(decode("\\d", &sep).is_err());
+        assert!(decode("\\|", &sep).is_err());
+        assert!(decode("trailing \\", &sep).is_err());
+        assert!(decode("\\u{110000}", &sep).is_err());
+        assert!(decode("\\u{A}", &sep).is_err());
+    }
+}
@@ -1,75 +1,67 @@
+use anyhow::{Result, bail};
 use std::fmt;
+use std::str::FromStr;
 
 /// A unique alias identifier for a file (e.g., "A", "AB", "XYZ")
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+///
+/// Aliases are numbered in bijective base 26: A is 0, Z is 25, AA is 26, and
+/// so on without limit. Comparing the numbers orders shorter aliases first,
+/// then alphabetically (A < Z < AA < AAA).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FileAlias {
-    /// The character string representation (stored as bytes for Copy)
-    /// Max 3 bytes for "ZZZ", so we use a fixed array
-    bytes: [u8; 3],
-    /// Actual length used (1-3)
-    len: u8,
+    index: u64,
 }
 
 impl FileAlias {
-    /// Create a new FileAlias from a slice of chars
+    /// Create a new FileAlias from a slice of chars (assumes A-Z)
     pub fn new(chars: &[char]) -> Self {
-        let len = chars.len().min(3);
-        let mut bytes = [0u8; 3];
-
-        for (i, &c) in chars.iter().take(len).enumerate() {
-            // Store ASCII byte for each char (assumes A-Z)
-            bytes[i] = c as u8;
-        }
-
+        let index = chars
+            .iter()
+            .fold(0u64, |acc, &c| acc * 26 + (c as u64 - 'A' as u64 + 1));
         Self {
-            bytes,
-            len: len as u8,
+            index: index.saturating_sub(1),
         }
     }
 
-    /// Create a new FileAlias from a string
-    pub fn from_str(s: impl AsRef<str>) -> Self {
-        let s = s.as_ref();
-        let len = s.len().min(3);
-        let mut bytes = [0u8; 3];
-        bytes[..len].copy_from_slice(&s.as_bytes()[..len]);
-
-        Self {
-            bytes,
-            len: len as u8,
-        }
+    /// The alias at position `index` in the sequence A, B, ... Z, AA, AB, ...
+    pub fn from_index(index: u64) -> Self {
+        Self { index }
     }
 
     /// Get the string value of this alias
     pub fn val(&self) -> String {
-        String::from_utf8_lossy(&self.bytes[..self.len as usize]).into_owned()
-    }
-
-    /// Get the string value as a &str
-    pub fn as_str(&self) -> &str {
-        // SAFETY: We only store valid ASCII letters A-Z
-        unsafe { std::str::from_utf8_unchecked(&self.bytes[..self.len as usize]) }
+        let mut letters = Vec::new();
+        let mut n = self.index + 1;
+        while n > 0 {
+            n -= 1;
+            letters.push((b'A' + (n % 26) as u8) as char);
+            n /= 26;
+        }
+        letters.iter().rev().collect()
     }
 }
 
-impl fmt::Display for FileAlias {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Use the string formatting to respect width, alignment, etc.
-        fmt::Display::fmt(self.as_str(), f)
-    }
-}
+impl FromStr for FileAlias {
+    type Err = anyhow::Error;
 
-impl PartialOrd for FileAlias {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_uppercase()) {
+            bail!("file aliases are made of the letters A-Z: {}", s);
+        }
+        let mut index: u64 = 0;
+        for b in s.bytes() {
+            index = index
+                .checked_mul(26)
+                .and_then(|i| i.checked_add((b - b'A' + 1) as u64))
+                .ok_or_else(|| anyhow::anyhow!("file alias is too long: {}", s))?;
+        }
+        Ok(Self { index: index - 1 })
     }
 }
 
-impl Ord for FileAlias {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // Compare by length first (A < AA < AAA), then lexicographically
-        self.len
-            .cmp(&other.len)
-            .then_with(|| self.bytes[..self.len as usize].cmp(&other.bytes[..other.len as usize]))
+impl fmt::Display for FileAlias {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Pad rather than write, to respect width, alignment, etc.
+        f.pad(&self.val())
     }
 }
@@ -1,78 +1,251 @@
-use crate::{alias_iter, Args, FileAlias, FileInfo, MatchLine};
-use anyhow::{Context, Result};
+use crate::editor::prompt_user;
+use crate::locations::{LineSpan, Location, LocationParser};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine, alias_iter};
+use anyhow::{Context, Result, bail};
 use camino::Utf8PathBuf;
+use itertools::Itertools;
+use log::debug;
 use rayon::prelude::*;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{self, Read};
-use std::{env, fs};
+use std::{env, fmt, fs};
 
-pub fn load_from_list(
-    list_path: &Utf8PathBuf,
+/// The most invalid entries listed before the report is cut short
+const MAX_REPORTED: usize = 20;
+
+/// A list entry which can't be loaded
+#[derive(Debug, PartialEq)]
+pub struct InvalidEntry {
+    /// The path, plus the line or range if the problem is with the lines
+    pub entry: String,
+    pub reason: Reason,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Reason {
+    NotFound,
+    NotUtf8,
+    Unreadable(String),
+    LineZero,
+    /// The entry starts after the last line of a file with this many lines
+    PastEnd(usize),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::NotFound => write!(f, "file not found"),
+            Reason::NotUtf8 => write!(f, "file is not valid UTF-8"),
+            Reason::Unreadable(err) => write!(f, "can't read file: {}", err),
+            Reason::LineZero => write!(f, "line numbers start at 1"),
+            Reason::PastEnd(len) => write!(f, "past the end of the file ({} lines)", len),
+        }
+    }
+}
+
+/// Load the entries of every --file list, plus any locations given as arguments
+pub fn load_from_lists(
     args: &Args,
+    filters: &Filters,
 ) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
-    let content = fs::read_to_string(list_path).context("reading list file")?;
-    let label = format!("File: {}", list_path);
-    let (matches, files) = parse_and_load(&content, args)?;
+    if args.file.iter().filter(|p| *p == "-").count() > 1 {
+        bail!("stdin can only be read once; pass '-' to --file a single time");
+    }
+
+    let mut content = String::new();
+    let mut sources = Vec::new();
+    for list_path in &args.file {
+        content.push_str(&read_input(list_path).context("reading list file")?);
+        content.push('\n');
+        sources.push(if list_path == "-" {
+            "STDIN".to_string()
+        } else {
+            list_path.to_string()
+        });
+    }
+
+    let mut parts = Vec::new();
+    if !sources.is_empty() {
+        parts.push(format!("File: {}", sources.join(", ")));
+    }
+    if !args.locations.is_empty() {
+        parts.push(format!("Locations: {}", args.locations.join(", ")));
+    }
+    let mut label = parts.join("; ");
+    if let Some(ref pattern) = args.pattern {
+        label.push_str(&format!("; Regex: {}", pattern));
+    }
+    let (matches, files) = parse_and_load(&content, args, filters)?;
     Ok((matches, files, label))
 }
 
-pub fn load_from_stdin(
-    args: &Args,
-) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
-    let mut buffer = String::new();
-    io::stdin()
-        .read_to_string(&mut buffer)
-        .context("reading from stdin")?;
-    let (matches, files) = parse_and_load(&buffer, args)?;
-    Ok((matches, files, "STDIN".to_string()))
+/// Read an input file, or stdin if the path is '-'
+pub fn read_input(path: &Utf8PathBuf) -> Result<String> {
+    if path == "-" {
+        let mut buffer = String::new();
+        io::stdin()
+            .read_to_string(&mut buffer)
+            .context("reading from stdin")?;
+        Ok(buffer)
+    } else {
+        fs::read_to_string(path).with_context(|| format!("reading {}", path))
+    }
 }
 
 fn parse_and_load(
     content: &str,
     args: &Args,
+    filters: &Filters,
 ) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>)> {
-    let mut requests = Vec::new();
+    let mut locations = Vec::new();
     let absolute_base = get_absolute_base(args)?;
+    let parser = match args.list_format {
+        Some(ref format) => LocationParser::from_format(format)?,
+        None => LocationParser::builtin(),
+    };
+    let mut skipped = 0;
 
-    for (idx, line) in content.lines().enumerate() {
+    for line in content.lines() {
         let line = line.trim();
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
 
-        let (path_str, line_str) = line
-            .rsplit_once(':')
-            .with_context(|| format!("missing colon separator on line {}", idx + 1))?;
-
-        let lineno = line_str.parse::<usize>().context("parsing line number")?;
-
-        let path = Utf8PathBuf::from(path_str);
-        let full_path = if path.is_absolute() {
-            path
+        if let Some(loc) = parser.parse(line) {
+            locations.push(loc);
+        } else if resolve_path(&absolute_base, line).is_file() {
+            // A bare path means every line in the file
+            locations.push(Location {
+                path: line.to_string(),
+                lines: LineSpan::All,
+                message: None,
+            });
         } else {
-            absolute_base.join(path)
-        };
+            // Tool output is often interleaved with other text, like source
+            // snippets under a compiler error. Skip anything without a location.
+            debug!("Skipping list entry without a location: {}", line);
+            skipped += 1;
+        }
+    }
+
+    if skipped > 0 {
+        eprintln!(
+            "Skipped {} list line(s) without a path:line location",
+            skipped
+        );
+    }
 
-        requests.push((full_path, lineno));
+    // Unlike list lines, an argument without a line is always loaded whole,
+    // so that a typo is reported rather than skipped
+    let argument = LocationParser::argument();
+    for location in &args.locations {
+        locations.push(argument.parse(location).unwrap_or_else(|| Location {
+            path: location.clone(),
+            lines: LineSpan::All,
+            message: None,
+        }));
     }
 
+    load_locations(locations, args, filters)
+}
+
+/// Load the files named by `locations` and build a MatchLine for each line
+/// they refer to. Relative paths are resolved against --working-directory.
+/// Tools often report several problems on one line, so locations on the same
+/// line are merged into one, with their messages combined.
+pub fn load_locations(
+    locations: Vec<Location>,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>)> {
+    let absolute_base = get_absolute_base(args)?;
+    let requests: Vec<_> = locations
+        .into_iter()
+        .map(|loc| {
+            (
+                resolve_path(&absolute_base, &loc.path),
+                loc.lines,
+                loc.message,
+            )
+        })
+        .collect();
+
     let unique_paths: Vec<Utf8PathBuf> = requests
         .iter()
-        .map(|(p, _)| p.clone())
+        .map(|(p, _, _)| p.clone())
         .collect::<HashSet<_>>()
         .into_iter()
         .collect();
 
-    let file_infos = load_files_parallel(unique_paths)?;
-    let (files, path_to_alias) = assign_aliases(file_infos);
-    let match_lines = build_match_lines(requests, &files, &path_to_alias);
+    let (file_infos, mut invalid) = load_files_parallel(unique_paths);
+    let (mut files, path_to_alias) = assign_aliases(file_infos);
+    let (mut match_lines, invalid_lines, clipped) =
+        build_match_lines(requests, &files, &path_to_alias);
+    invalid.extend(invalid_lines);
+    match_lines.retain(|m| filters.keeps_content(&m.original_content));
+    retain_matched(&mut files, &match_lines);
+
+    if !clipped.is_empty() {
+        eprintln!(
+            "{} range(s) run past the end of the file, so only the lines up to it are loaded:",
+            clipped.len()
+        );
+        report(
+            clipped
+                .iter()
+                .map(|(entry, len)| (entry, format!("the file has {} lines", len))),
+        );
+    }
+    if !invalid.is_empty() {
+        eprintln!("{} list entries can't be loaded:", invalid.len());
+        report(invalid.iter().map(|i| (&i.entry, i.reason.to_string())));
+        let valid = match_lines.len();
+        if valid > 0
+            && !args.skip_invalid
+            && !prompt_user(format!("Continue with the {} valid line(s)?", valid))?
+        {
+            bail!("aborted with {} invalid list entries", invalid.len());
+        }
+    }
 
     Ok((match_lines, files))
 }
 
+/// Drop the files which have no lines left, so they aren't listed or counted
+pub fn retain_matched(files: &mut BTreeMap<FileAlias, FileInfo>, match_lines: &[MatchLine]) {
+    let aliases: HashSet<FileAlias> = match_lines.iter().map(|m| m.alias).collect();
+    files.retain(|alias, _| aliases.contains(alias));
+}
+
+/// List entries with a note beside each, up to `MAX_REPORTED` of them
+fn report<'a>(entries: impl ExactSizeIterator<Item = (&'a String, String)>) {
+    let total = entries.len();
+    let shown: Vec<_> = entries.take(MAX_REPORTED).collect();
+    let width = shown
+        .iter()
+        .map(|(entry, _)| entry.len())
+        .max()
+        .unwrap_or_default();
+    for (entry, note) in shown {
+        eprintln!("  {:<width$}  {}", entry, note);
+    }
+    if total > MAX_REPORTED {
+        eprintln!("  ... and {} more", total - MAX_REPORTED);
+    }
+}
+
+pub fn resolve_path(base: &Utf8PathBuf, path_str: &str) -> Utf8PathBuf {
+    let path = Utf8PathBuf::from(path_str);
+    if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
 /// Determines the absolute base directory.
 /// If --working-directory is provided, it's resolved against CWD. If not, CWD is used.
-fn get_absolute_base(args: &Args) -> Result<Utf8PathBuf> {
+pub fn get_absolute_base(args: &Args) -> Result<Utf8PathBuf> {
     let cwd = Utf8PathBuf::try_from(env::current_dir()?)?;
 
     if let Some(ref wd) = args.working_directory {
@@ -86,25 +259,44 @@ fn get_absolute_base(args: &Args) -> Result<Utf8PathBuf> {
     }
 }
 
-fn load_files_parallel(paths: Vec<Utf8PathBuf>) -> Result<Vec<FileInfo>> {
-    paths
+/// Read every file, setting aside those which can't be edited rather than
+/// failing the whole load
+fn load_files_parallel(paths: Vec<Utf8PathBuf>) -> (Vec<FileInfo>, Vec<InvalidEntry>) {
+    let results: Vec<_> = paths
         .into_par_iter()
         .map(|full_path| {
-            let content = fs::read_to_string(&full_path)
-                .with_context(|| format!("failed to read file: {}", full_path))?;
-            let metadata = fs::metadata(&full_path)?;
-
-            Ok(FileInfo {
-                // We store the full absolute path in both fields to satisfy
-                // the requirement that the alias section shows absolute paths.
-                path: full_path.clone(),
-                full_path,
-                alias: FileAlias::new(&['A']),
-                original_content: content,
-                original_mtime: metadata.modified()?,
+            let read = fs::read(&full_path).and_then(|bytes| {
+                let mtime = fs::metadata(&full_path)?.modified()?;
+                Ok((bytes, mtime))
+            });
+            let reason = match read {
+                Ok((bytes, mtime)) => match String::from_utf8(bytes) {
+                    Ok(content) => {
+                        return Ok(FileInfo {
+                            // We store the full absolute path in both fields to satisfy
+                            // the requirement that the alias section shows absolute paths.
+                            path: full_path.clone(),
+                            full_path,
+                            alias: FileAlias::new(&['A']),
+                            original_content: content,
+                            original_mtime: mtime,
+                        });
+                    }
+                    Err(_) => Reason::NotUtf8,
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Reason::NotFound,
+                Err(e) => Reason::Unreadable(e.to_string()),
+            };
+            Err(InvalidEntry {
+                entry: full_path.into_string(),
+                reason,
             })
         })
-        .collect()
+        .collect();
+
+    let (infos, mut invalid): (Vec<_>, Vec<_>) = results.into_iter().partition_result();
+    invalid.sort_by(|a, b| a.entry.cmp(&b.entry));
+    (infos, invalid)
 }
 
 fn assign_aliases(
@@ -116,35 +308,94 @@ fn assign_aliases(
     infos.sort_by(|a, b| a.path.cmp(&b.path));
     let mut files = BTreeMap::new();
     let mut path_map = BTreeMap::new();
-    let mut aliases = alias_iter();
 
-    for mut info in infos {
-        if let Some(alias) = aliases.next() {
-            path_map.insert(info.full_path.clone(), alias);
-            info.alias = alias;
-            files.insert(alias, info);
-        }
+    for (mut info, alias) in infos.into_iter().zip(alias_iter()) {
+        path_map.insert(info.full_path.clone(), alias);
+        info.alias = alias;
+        files.insert(alias, info);
     }
     (files, path_map)
 }
 
+/// Expand each request into MatchLines. Entries which are out of range are
+/// returned separately, as are ranges cut short at the end of their file,
+/// with the file's length. A line named again is merged into the earlier one,
+/// adding its message to the earlier line's.
 fn build_match_lines(
-    reqs: Vec<(Utf8PathBuf, usize)>,
+    reqs: Vec<(Utf8PathBuf, LineSpan, Option<String>)>,
     files: &BTreeMap<FileAlias, FileInfo>,
     path_map: &BTreeMap<Utf8PathBuf, FileAlias>,
-) -> Vec<MatchLine> {
-    reqs.into_iter()
-        .filter_map(|(path, lineno)| {
-            let alias = path_map.get(&path)?;
-            let file = files.get(alias)?;
-            let line_content = file.original_content.lines().nth(lineno - 1)?;
-            Some(MatchLine {
-                alias: *alias,
-                lineno,
-                original_content: line_content.to_string(),
-            })
-        })
-        .collect()
+) -> (Vec<MatchLine>, Vec<InvalidEntry>, Vec<(String, usize)>) {
+    let mut match_lines: Vec<MatchLine> = Vec::new();
+    let mut invalid = Vec::new();
+    let mut clipped = Vec::new();
+    let mut seen: HashMap<(FileAlias, usize), usize> = HashMap::new();
+
+    for (path, span, mut message) in reqs {
+        // Files which failed to load have already been reported
+        let Some(file) = path_map.get(&path).and_then(|alias| files.get(alias)) else {
+            continue;
+        };
+        let lines: Vec<&str> = file.original_content.split_terminator('\n').collect();
+        let (start, end) = match span {
+            LineSpan::Range(start, end) => (start, end.min(lines.len())),
+            LineSpan::All => (1, lines.len()),
+        };
+        let entry = || match span {
+            LineSpan::Range(s, e) if s == e => format!("{}:{}", path, s),
+            LineSpan::Range(s, e) => format!("{}:{}-{}", path, s, e),
+            LineSpan::All => path.to_string(),
+        };
+
+        if start == 0 {
+            invalid.push(InvalidEntry {
+                entry: entry(),
+                reason: Reason::LineZero,
+            });
+            continue;
+        }
+        if start > lines.len() && span != LineSpan::All {
+            invalid.push(InvalidEntry {
+                entry: entry(),
+                reason: Reason::PastEnd(lines.len()),
+            });
+            continue;
+        }
+        if let LineSpan::Range(_, requested_end) = span
+            && requested_end > end
+        {
+            clipped.push((entry(), end));
+        }
+
+        for lineno in start..=end {
+            let line_content = lines[lineno - 1];
+            // A message belongs to the first line of a range. grep and vimgrep
+            // repeat the line itself, which isn't worth showing twice.
+            let note = message
+                .take()
+                .filter(|msg| msg.trim() != line_content.trim());
+
+            if let Some(&idx) = seen.get(&(file.alias, lineno)) {
+                let existing: &mut MatchLine = &mut match_lines[idx];
+                if let Some(note) = note
+                    && existing.annotation.as_deref() != Some(note.as_str())
+                {
+                    let text = existing.annotation.get_or_insert_default();
+                    if !text.is_empty() {
+                        text.push('\n');
+                    }
+                    text.push_str(&note);
+                }
+                continue;
+            }
+
+            let mut m = MatchLine::new(file.alias, lineno, line_content.to_string());
+            m.annotation = note;
+            seen.insert((file.alias, lineno), match_lines.len());
+            match_lines.push(m);
+        }
+    }
+    (match_lines, invalid, clipped)
 }
 
 #[cfg(test)]
@@ -156,7 +407,7 @@ mod tests {
     #[test]
     fn test_relative_path_resolution() {
         let dir = tempdir().unwrap();
-        let wd = Utf8PathBuf::try_from(dir.path().to_path_buf()).unwrap();
+        let wd = dir.path().to_path_buf();
 
         let target = wd.join("target.txt");
         fs::write(&target, "content").unwrap();
@@ -164,8 +415,9 @@ mod tests {
         let list_path = wd.join("list.txt");
         fs::write(&list_path, "target.txt:1").unwrap();
 
-        let args = Args::parse_from(&["okapi", "-w", wd.as_str(), "--file", list_path.as_str()]);
-        let (matches, files, _) = load_from_list(&list_path, &args).unwrap();
+        let args = Args::parse_from(["okapi", "-w", wd.as_str(), "--file", list_path.as_str()]);
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, files, _) = load_from_lists(&args, &filters).unwrap();
 
         let alias = matches[0].alias;
         let info = files.get(&alias).unwrap();
@@ -174,4 +426,178 @@ mod tests {
         assert!(info.full_path.is_absolute());
         assert!(info.full_path.ends_with("target.txt"));
     }
+
+    #[test]
+    fn test_list_content_filters() {
+        let dir = tempdir().unwrap();
+        let wd = dir.path().to_path_buf();
+        fs::write(wd.join("target.txt"), "Hamilton\nALEXANDER Hamilton\n").unwrap();
+
+        let args = Args::parse_from([
+            "okapi",
+            "-w",
+            wd.as_str(),
+            "--file",
+            "-",
+            "--exclude",
+            "alexander",
+            "--smart-case",
+        ]);
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, _) = parse_and_load("target.txt:1\ntarget.txt:2", &args, &filters).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].lineno, 1);
+    }
+
+    #[test]
+    fn test_ranges_and_whole_files() {
+        let dir = tempdir().unwrap();
+        let wd = dir.path().to_path_buf();
+        fs::write(wd.join("a.txt"), "1\n2\n3\n4\n5\n").unwrap();
+        fs::write(wd.join("b.txt"), "one\ntwo\n").unwrap();
+
+        // a.txt:4+9 runs past the end, which is reported but still loads lines 4 and 5
+        let args = Args::parse_from(["okapi", "-w", wd.as_str(), "--file", "-", "--skip-invalid"]);
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, _) = parse_and_load("a.txt:2-3\na.txt:4+9\nb.txt", &args, &filters).unwrap();
+
+        let found: Vec<&str> = matches
+            .iter()
+            .map(|m| m.original_content.as_str())
+            .collect();
+        assert_eq!(found, vec!["2", "3", "4", "5", "one", "two"]);
+    }
+
+    #[test]
+    fn test_compiler_output() {
+        let dir = tempdir().unwrap();
+        let wd = dir.path().to_path_buf();
+        fs::write(wd.join("a.c"), "int main() {\n  int x;\n}\n").unwrap();
+
+        let output = "a.c: In function 'main':\n\
+            a.c:2:7: warning: unused variable 'x'\n\
+            \x20   2 |   int x;\n";
+        let args = Args::parse_from(["okapi", "-w", wd.as_str(), "--file", "-"]);
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, _) = parse_and_load(output, &args, &filters).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].original_content, "  int x;");
+        assert_eq!(
+            matches[0].annotation.as_deref(),
+            Some("warning: unused variable 'x'")
+        );
+    }
+
+    #[test]
+    fn test_merged_lists_and_pattern() {
+        let dir = tempdir().unwrap();
+        let wd = dir.path().to_path_buf();
+        fs::write(wd.join("a.txt"), "TODO one\ntwo\n").unwrap();
+        fs::write(wd.join("b.txt"), "todo three\nTODO four\n").unwrap();
+        fs::write(wd.join("list1.txt"), "a.txt:1\na.txt:2\n").unwrap();
+        fs::write(wd.join("list2.txt"), "b.txt:1\n").unwrap();
+
+        let mut args = Args::parse_from([
+            "okapi",
+            "-w",
+            wd.as_str(),
+            "TODO",
+            "--file",
+            wd.join("list1.txt").as_str(),
+            "--file",
+            wd.join("list2.txt").as_str(),
+            "b.txt:2",
+        ]);
+        args.resolve_positionals();
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, _, label) = load_from_lists(&args, &filters).unwrap();
+
+        let found: Vec<&str> = matches
+            .iter()
+            .map(|m| m.original_content.as_str())
+            .collect();
+        assert_eq!(found, vec!["TODO one", "TODO four"]);
+        assert!(label.ends_with("list2.txt; Locations: b.txt:2; Regex: TODO"));
+
+        // Locations can be given without a list or a pattern, and files left
+        // with no lines are dropped
+        let mut args = Args::parse_from(["okapi", "-w", wd.as_str(), "a.txt:2", "b.txt:1-2"]);
+        args.resolve_positionals();
+        assert_eq!(args.pattern, None);
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, files, label) = load_from_lists(&args, &filters).unwrap();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(files.len(), 2);
+        assert_eq!(label, "Locations: a.txt:2, b.txt:1-2");
+
+        let mut args =
+            Args::parse_from(["okapi", "-w", wd.as_str(), "four", "a.txt:2", "b.txt:1-2"]);
+        args.resolve_positionals();
+        let filters = Filters::from_args(&args).unwrap();
+        let (matches, files, _) = load_from_lists(&args, &filters).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(files.len(), 1);
+
+        // Without a location, the arguments are a search as usual
+        let mut args = Args::parse_from(["okapi", "TODO", "a.txt", "src"]);
+        args.resolve_positionals();
+        assert!(!args.list_mode());
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        let dir = tempdir().unwrap();
+        let wd = dir.path().to_path_buf();
+        fs::write(wd.join("a.txt"), "one\ntwo\n").unwrap();
+        fs::write(wd.join("bin.dat"), b"\xff\xfe\n").unwrap();
+
+        let reqs: Vec<_> = [
+            ("a.txt", LineSpan::Range(0, 0), None),
+            ("a.txt", LineSpan::Range(2, 2), Some("first")),
+            ("a.txt", LineSpan::Range(1, 2), Some("second")),
+            ("a.txt", LineSpan::Range(5, 9), None),
+            ("a.txt", LineSpan::Range(2, 4), None),
+            ("bin.dat", LineSpan::Range(1, 1), None),
+            ("gone.txt", LineSpan::Range(1, 1), None),
+            ("a.txt", LineSpan::Range(2, 2), Some("again")),
+        ]
+        .into_iter()
+        .map(|(p, span, msg)| (wd.join(p), span, msg.map(str::to_string)))
+        .collect();
+
+        let paths = reqs.iter().map(|r| r.0.clone()).unique().collect();
+        let (infos, mut invalid) = load_files_parallel(paths);
+        let (files, path_map) = assign_aliases(infos);
+        let (matches, invalid_lines, clipped) = build_match_lines(reqs, &files, &path_map);
+        invalid.extend(invalid_lines);
+
+        let found: Vec<(usize, Option<&str>)> = matches
+            .iter()
+            .map(|m| (m.lineno, m.annotation.as_deref()))
+            .collect();
+        // Repeats of line 2 are merged, with their messages combined
+        assert_eq!(found, vec![(2, Some("first\nagain")), (1, Some("second"))]);
+
+        let reasons: Vec<(String, &Reason)> = invalid
+            .iter()
+            .map(|i| {
+                (
+                    i.entry.strip_prefix(wd.as_str()).unwrap().to_string(),
+                    &i.reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("/bin.dat".to_string(), &Reason::NotUtf8),
+                ("/gone.txt".to_string(), &Reason::NotFound),
+                ("/a.txt:0".to_string(), &Reason::LineZero),
+                ("/a.txt:5-9".to_string(), &Reason::PastEnd(2)),
+            ]
+        );
+        assert_eq!(clipped, vec![(wd.join("a.txt:2-4").to_string(), 2)]);
+    }
 }
@@ -0,0 +1,416 @@
+use crate::normalize::fold;
+use crate::search::parse_column_range;
+use crate::{Args, FileAlias, FileInfo, MatchLine};
+use anyhow::{Context, Result};
+use fancy_regex::{Regex, RegexBuilder};
+use log::{debug, warn};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A secondary pattern, like --exclude or --near. These are compiled with
+/// fancy-regex, which accepts the PCRE-style lookarounds and backreferences
+/// available to the main pattern with `-- -P`, as well as Rust regex syntax.
+#[derive(Debug)]
+pub struct FilterPattern {
+    re: Regex,
+    /// With --normalize or --ignore-diacritics, the pattern and the text it's
+    /// matched against are folded, just like the main pattern. Holds whether
+    /// diacritics are stripped.
+    fold: Option<bool>,
+}
+
+impl FilterPattern {
+    /// Compile a pattern with the same options ripgrep is given for the main
+    /// pattern: --ignore-case, --smart-case, --fixed-strings, --word-regexp and
+    /// --multiline. --normalize and --ignore-diacritics apply too.
+    pub fn new(pattern: &str, args: &Args) -> Result<Self> {
+        let fold_with =
+            (args.normalize || args.ignore_diacritics).then_some(args.ignore_diacritics);
+        let pattern = match fold_with {
+            Some(strip_diacritics) => Cow::Owned(fold(pattern, strip_diacritics)),
+            None => Cow::Borrowed(pattern),
+        };
+        let mut source = if args.fixed_strings {
+            regex::escape(&pattern)
+        } else {
+            pattern.to_string()
+        };
+        if args.word_regexp {
+            // Like `rg -w`: the match may not be flanked by word characters
+            source = format!(r"(?<!\w)(?:{})(?!\w)", source);
+        }
+        // Like ripgrep, ^ and $ always match at line boundaries. --multiline only
+        // decides what text a pattern sees, like a whole --near window.
+        let re = RegexBuilder::new(&source)
+            .case_insensitive(case_insensitive(&pattern, args))
+            .multi_line(true)
+            .build()
+            .with_context(|| format!("invalid filter pattern: {}", pattern))?;
+        Ok(Self {
+            re,
+            fold: fold_with,
+        })
+    }
+
+    fn prepare<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self.fold {
+            Some(strip_diacritics) => Cow::Owned(fold(text, strip_diacritics)),
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// Byte offsets where each match in `text` starts. When the pattern folds
+    /// text, these are offsets into the folded text.
+    pub fn match_starts(&self, text: &str) -> Vec<usize> {
+        self.re
+            .find_iter(self.prepare(text).as_ref())
+            .filter_map(|m| m.ok())
+            .map(|m| m.start())
+            .collect()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        // Runtime errors only come from pathological backtracking. Treat them as
+        // a non-match rather than aborting the whole session.
+        self.re
+            .is_match(self.prepare(text).as_ref())
+            .unwrap_or_else(|e| {
+                warn!("Pattern {} failed on {:?}: {}", self.re, text, e);
+                false
+            })
+    }
+}
+
+/// Whether a pattern should match case-insensitively. Smart case follows
+/// ripgrep: insensitive unless the pattern has an uppercase letter.
+pub fn case_insensitive(pattern: &str, args: &Args) -> bool {
+    args.ignore_case || (args.smart_case && !has_uppercase_literal(pattern, args.fixed_strings))
+}
+
+/// Whether a pattern has an uppercase letter of its own. Escapes like `\W`,
+/// `\S` or `\p{Lu}` are classes rather than letters, so they don't count
+/// unless the pattern is a fixed string.
+fn has_uppercase_literal(pattern: &str, fixed_strings: bool) -> bool {
+    if fixed_strings {
+        return pattern.chars().any(char::is_uppercase);
+    }
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                // \p{Greek}, \x{41} and \u{41} take their argument in braces,
+                // and \pL takes a single letter
+                if let Some('p' | 'P' | 'x' | 'u' | 'U') = chars.next() {
+                    if chars.next_if_eq(&'{').is_some() {
+                        chars.by_ref().find(|&c| c == '}');
+                    } else {
+                        chars.next();
+                    }
+                }
+            }
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// A --near or --not-near argument: a pattern and how many lines away it may be
+#[derive(Debug, Clone)]
+pub struct ProximitySpec {
+    pub pattern: String,
+    pub distance: usize,
+}
+
+/// Parse "PATTERN:N". The last colon separates the distance, so the pattern
+/// itself may contain colons.
+pub fn parse_proximity(s: &str) -> Result<ProximitySpec, String> {
+    let (pattern, distance) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected PATTERN:N, got '{}'", s))?;
+    let distance = distance
+        .parse::<usize>()
+        .map_err(|_| format!("invalid line distance '{}'", distance))?;
+
+    Ok(ProximitySpec {
+        pattern: pattern.to_string(),
+        distance,
+    })
+}
+
+#[derive(Debug)]
+struct ProximityFilter {
+    re: FilterPattern,
+    distance: usize,
+    /// --not-near: keep only matches with NO line nearby matching `re`
+    negate: bool,
+    /// --multiline: match against the whole window, so the pattern may span lines
+    multiline: bool,
+}
+
+impl ProximityFilter {
+    fn keep(&self, lines: &[&str], lineno: usize) -> bool {
+        let idx = lineno.saturating_sub(1);
+        let start = idx.saturating_sub(self.distance);
+        let end = (idx + self.distance + 1).min(lines.len());
+        let found = lines.get(start..end).is_some_and(|window| {
+            if self.multiline {
+                self.re.is_match(&window.join("\n"))
+            } else {
+                window.iter().any(|l| self.re.is_match(l))
+            }
+        });
+
+        found != self.negate
+    }
+}
+
+/// Every secondary pattern given on the command line
+#[derive(Debug)]
+pub struct Filters {
+    exclude: Vec<FilterPattern>,
+    require: Vec<FilterPattern>,
+    proximity: Vec<ProximityFilter>,
+    /// The 1-indexed columns a match may start in, from --columns
+    columns: Option<Vec<usize>>,
+}
+
+impl Filters {
+    /// Compile all the filter patterns and parse --columns up front, so that a
+    /// bad one is reported before ripgrep spends time scanning the tree
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| FilterPattern::new(p, args))
+                .collect::<Result<Vec<_>>>()
+        };
+        let specs = args
+            .near
+            .iter()
+            .map(|s| (s, false))
+            .chain(args.not_near.iter().map(|s| (s, true)));
+        let proximity = specs
+            .map(|(spec, negate)| {
+                Ok(ProximityFilter {
+                    re: FilterPattern::new(&spec.pattern, args)?,
+                    distance: spec.distance,
+                    negate,
+                    multiline: args.multiline,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut require = compile(&args.require)?;
+        // With --file or locations, the pattern filters the listed lines instead of driving a search
+        if args.list_mode()
+            && let Some(ref pattern) = args.pattern
+        {
+            require.push(FilterPattern::new(pattern, args)?);
+        }
+
+        Ok(Self {
+            exclude: compile(&args.exclude)?,
+            require,
+            proximity,
+            columns: args
+                .columns
+                .as_deref()
+                .map(parse_column_range)
+                .transpose()?,
+        })
+    }
+
+    /// Whether the content of a matched line passes --exclude and --require
+    pub fn keeps_content(&self, content: &str) -> bool {
+        self.require.iter().all(|f| f.is_match(content))
+            && !self.exclude.iter().any(|f| f.is_match(content))
+    }
+
+    /// Whether a match starting in this 1-indexed column passes --columns
+    pub fn keeps_column(&self, column: usize) -> bool {
+        self.columns
+            .as_ref()
+            .is_none_or(|cols| cols.contains(&column))
+    }
+
+    /// How many matches a search should keep. Proximity filters need to see
+    /// every candidate, so with them --max-count is applied afterwards instead.
+    pub fn search_limit(&self, max_count: usize) -> usize {
+        if self.proximity.is_empty() {
+            max_count
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Drop matches which fail any --near or --not-near condition, then cap the
+    /// rest at `max_count`. Lines around each match are read from the file
+    /// contents loaded at the start of the session. Files left without a match
+    /// are dropped too.
+    pub fn apply_proximity(
+        &self,
+        match_lines: Vec<MatchLine>,
+        files: &mut BTreeMap<FileAlias, FileInfo>,
+        max_count: usize,
+    ) -> Vec<MatchLine> {
+        if self.proximity.is_empty() {
+            return match_lines;
+        }
+
+        let mut file_lines: HashMap<FileAlias, Vec<&str>> = HashMap::new();
+        let kept: Vec<MatchLine> = match_lines
+            .into_iter()
+            .filter(|m| {
+                let Some(file) = files.get(&m.alias) else {
+                    return false;
+                };
+                let lines = file_lines
+                    .entry(m.alias)
+                    .or_insert_with(|| file.original_content.lines().collect());
+
+                let keep = self.proximity.iter().all(|f| f.keep(lines, m.lineno));
+                if !keep {
+                    debug!("Excluding {}:{} - proximity filter", file.path, m.lineno);
+                }
+                keep
+            })
+            .take(max_count)
+            .collect();
+
+        let aliases: HashSet<FileAlias> = kept.iter().map(|m| m.alias).collect();
+        files.retain(|alias, _| aliases.contains(alias));
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_proximity() {
+        let spec = parse_proximity("// SAFETY:3").unwrap();
+        assert_eq!(spec.pattern, "// SAFETY");
+        assert_eq!(spec.distance, 3);
+
+        let spec = parse_proximity("a:b:0").unwrap();
+        assert_eq!(spec.pattern, "a:b");
+
+        assert!(parse_proximity("no distance").is_err());
+        assert!(parse_proximity("x:-1").is_err());
+    }
+
+    #[test]
+    fn test_fancy_exclude() {
+        let args = Args::parse_from([
+            "okapi",
+            "Gym",
+            "--exclude",
+            r"(?<=The)\sGym",
+            "--require",
+            r"(\w)\1",
+        ]);
+        let filters = Filters::from_args(&args).unwrap();
+
+        assert!(filters.keeps_content("Jimmy's Gym"));
+        assert!(!filters.keeps_content("The Gym"));
+        // No doubled letter
+        assert!(!filters.keeps_content("Jim's Gym"));
+
+        let args = Args::parse_from(["okapi", "Gym", "--exclude", "(?<=The"]);
+        assert!(Filters::from_args(&args).is_err());
+
+        let args = Args::parse_from(["okapi", "Gym", "--columns", "5..x"]);
+        assert!(Filters::from_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_proximity_window() {
+        let args = Args::parse_from(["okapi", "unwrap"]);
+        let lines = vec!["// SAFETY: fine", "", "", "x.unwrap()", "", "y.unwrap()"];
+        let near = ProximityFilter {
+            re: FilterPattern::new("SAFETY", &args).unwrap(),
+            distance: 3,
+            negate: false,
+            multiline: false,
+        };
+        assert!(near.keep(&lines, 4));
+        assert!(!near.keep(&lines, 6));
+
+        let not_near = ProximityFilter {
+            negate: true,
+            ..near
+        };
+        assert!(!not_near.keep(&lines, 4));
+        assert!(not_near.keep(&lines, 6));
+
+        let spanning = ProximityFilter {
+            re: FilterPattern::new(r"fine\n\n", &args).unwrap(),
+            distance: 1,
+            negate: false,
+            multiline: true,
+        };
+        assert!(spanning.keep(&lines, 2));
+        assert!(!spanning.keep(&lines, 4));
+    }
+
+    #[test]
+    fn test_proximity_before_max_count() {
+        let args = Args::parse_from(["okapi", "unwrap", "--near", "SAFETY:1"]);
+        let filters = Filters::from_args(&args).unwrap();
+        let (a, b) = (FileAlias::new(&['A']), FileAlias::new(&['B']));
+        let mut files = test_files(&[
+            ("A", "x.unwrap()\n\n// SAFETY: ok\ny.unwrap()"),
+            ("B", "z.unwrap()"),
+        ]);
+        let match_lines = vec![
+            MatchLine::new(a, 1, "x.unwrap()".into()),
+            MatchLine::new(a, 4, "y.unwrap()".into()),
+            MatchLine::new(b, 1, "z.unwrap()".into()),
+        ];
+
+        // The first match fails the filter, so it doesn't use up the one allowed
+        let kept = filters.apply_proximity(match_lines, &mut files, 1);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].lineno, 4);
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec![&a]);
+    }
+
+    #[test]
+    fn test_pattern_options() {
+        let matches = |flags: &[&str], pattern: &str, text: &str| {
+            let args = Args::parse_from(["okapi", "x"].iter().chain(flags));
+            FilterPattern::new(pattern, &args).unwrap().is_match(text)
+        };
+
+        assert!(matches(&["--smart-case"], "gym", "The GYM"));
+        assert!(!matches(&["--smart-case"], "Gym", "The GYM"));
+        // Escapes aren't uppercase letters
+        assert!(matches(&["--smart-case"], r"\Wgym\S", "The GYM!"));
+        assert!(matches(&["--smart-case"], r"\p{Greek}\x{41}gym", "αAGYM"));
+        assert!(!matches(&["--smart-case"], r"\pLGym", "aGYM"));
+        // The last of --ignore-case and --smart-case wins
+        assert!(!matches(
+            &["--ignore-case", "--smart-case"],
+            "Gym",
+            "The GYM"
+        ));
+        assert!(matches(
+            &["--smart-case", "--ignore-case"],
+            "Gym",
+            "The GYM"
+        ));
+
+        // ^ and $ match at line boundaries, with or without --multiline
+        assert!(matches(&[], "^b$", "a\nb"));
+
+        assert!(matches(&["--fixed-strings"], "a.b(", "x a.b( y"));
+        assert!(!matches(&["--fixed-strings"], "a.b", "axb"));
+
+        assert!(matches(&["--word-regexp"], "id", "user id"));
+        assert!(!matches(&["--word-regexp"], "id", "user_id"));
+    }
+}
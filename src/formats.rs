@@ -0,0 +1,492 @@
+use crate::{FileAlias, MatchLine};
+use anyhow::{Context, Result, bail};
+use camino::Utf8Path;
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+
+/// The layout of the editing buffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BufferFormat {
+    /// One line per match, marked with a shade block
+    #[default]
+    Text,
+    /// Columns path, line, original, new, delete and note, for spreadsheets
+    Csv,
+    /// One JSON object per line, with the same fields as CSV
+    Jsonl,
+    /// Only the line contents. Where each line came from is kept in a sidecar file.
+    Raw,
+}
+
+impl BufferFormat {
+    /// The buffer's file extension, so editors pick the right mode
+    pub fn extension(self) -> &'static str {
+        match self {
+            BufferFormat::Text => "txt",
+            BufferFormat::Csv => "csv",
+            BufferFormat::Jsonl => "jsonl",
+            BufferFormat::Raw => "txt",
+        }
+    }
+}
+
+/// The characters between a text buffer row's line number and its content.
+/// They alternate with each file, so the lines of one are easy to pick out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Separator {
+    pub heavy: char,
+    pub light: char,
+}
+
+impl Separator {
+    pub fn chars(self) -> [char; 2] {
+        [self.heavy, self.light]
+    }
+
+    /// A regex class matching the separators, and the '+' and '-' which replace them
+    pub fn marker_class(self) -> String {
+        let chars: String = self
+            .chars()
+            .iter()
+            .map(|c| regex::escape(&c.to_string()))
+            .collect();
+        format!("[{}+-]", chars)
+    }
+}
+
+/// Parse "shade" (▓░), "ascii" (|:), or one or two characters of your own
+pub fn parse_separator(s: &str) -> Result<Separator, String> {
+    let chars: Vec<char> = match s {
+        "shade" => vec!['▓', '░'],
+        "ascii" => vec!['|', ':'],
+        s => s.chars().collect(),
+    };
+    let (heavy, light) = match chars[..] {
+        [c] => (c, c),
+        [heavy, light] => (heavy, light),
+        _ => {
+            return Err(format!(
+                "expected 'shade', 'ascii' or 1-2 characters, got '{}'",
+                s
+            ));
+        }
+    };
+    // These already mean something in a row
+    if let Some(c) = [heavy, light]
+        .into_iter()
+        .find(|c| c.is_whitespace() || c.is_alphanumeric() || "+->\\".contains(*c))
+    {
+        return Err(format!("'{}' can't be used as a separator", c));
+    }
+    Ok(Separator { heavy, light })
+}
+
+/// What a row read back from the buffer does to its line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mark {
+    /// Replace the line with the row's content, exactly, even if it's blank
+    Edit,
+    /// Remove the line
+    Delete,
+    /// Add the row's content as a new line above the line
+    InsertAbove,
+    /// Add the row's content as a new line below the line
+    InsertBelow,
+    /// Remove the line, and put the row's content at `lineno` of the file with
+    /// `prefix`, or of the same file if there isn't one
+    MoveTo {
+        prefix: Option<String>,
+        lineno: usize,
+    },
+}
+
+/// An editable row read back from the buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The file prefix, as written by `prefix::assign`
+    pub prefix: String,
+    pub lineno: usize,
+    pub mark: Mark,
+    pub content: String,
+}
+
+impl Entry {
+    pub fn new(prefix: impl Into<String>, lineno: usize, content: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            lineno,
+            mark: Mark::Edit,
+            content: content.into(),
+        }
+    }
+}
+
+const HEADERS: [&str; 6] = ["path", "line", "original", "new", "delete", "note"];
+
+/// A raw buffer line holding only this deletes its line. An emptied line is
+/// written back empty, as in every other format.
+pub const RAW_DELETE: &str = "<<delete>>";
+
+/// Write one row per match. The "new" column starts out as the original line,
+/// or the suggested replacement if there is one. Marking the "delete" column
+/// deletes the line. The "note" column holds the line's annotation, like a
+/// compiler message, and is ignored when read back.
+pub fn write_csv(
+    tmp: &Utf8Path,
+    match_lines: &[MatchLine],
+    paths: &BTreeMap<FileAlias, String>,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_path(tmp)?;
+    writer.write_record(HEADERS)?;
+    for m in match_lines {
+        writer.write_record([
+            paths[&m.alias].as_str(),
+            &m.lineno.to_string(),
+            &m.original_content,
+            m.replacement.as_ref().unwrap_or(&m.original_content),
+            "",
+            m.annotation.as_deref().unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_jsonl(
+    tmp: &Utf8Path,
+    match_lines: &[MatchLine],
+    paths: &BTreeMap<FileAlias, String>,
+) -> Result<()> {
+    let mut file = fs::File::create(tmp)?;
+    for m in match_lines {
+        // Written field by field to keep the same order as the CSV columns
+        let fields = [
+            Value::from(paths[&m.alias].as_str()),
+            Value::from(m.lineno),
+            Value::from(m.original_content.as_str()),
+            Value::from(
+                m.replacement
+                    .as_ref()
+                    .unwrap_or(&m.original_content)
+                    .as_str(),
+            ),
+            Value::from(false),
+            Value::from(m.annotation.as_deref()),
+        ];
+        let record = HEADERS
+            .iter()
+            .zip(fields)
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .join(",");
+        writeln!(file, "{{{}}}", record)?;
+    }
+    Ok(())
+}
+
+/// Write just the contents of each line. The index records the path, line
+/// number and starting text of each, as JSON lines.
+pub fn write_raw(
+    tmp: &Utf8Path,
+    index: &Utf8Path,
+    match_lines: &[MatchLine],
+    paths: &BTreeMap<FileAlias, String>,
+) -> Result<()> {
+    let mut file = fs::File::create(tmp)?;
+    for m in match_lines {
+        // A '\r' ending the line is left out, and put back when it's read
+        let content = m.replacement.as_ref().unwrap_or(&m.original_content);
+        writeln!(file, "{}", content.strip_suffix('\r').unwrap_or(content))?;
+    }
+    write_jsonl(index, match_lines, paths)
+}
+
+/// Read rows back by column name, so that columns may be reordered or added.
+/// Spreadsheets in some locales save with semicolons, which are accepted too.
+/// Any text in the "delete" column deletes the line; an empty "new" cell
+/// empties it.
+pub fn parse_csv(text: &str) -> Result<Vec<Entry>> {
+    let header = text.lines().next().unwrap_or_default();
+    let delimiter = if header.contains(';') && !header.contains(',') {
+        b';'
+    } else {
+        b','
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+            .with_context(|| format!("CSV buffer has no '{}' column", name))
+    };
+    let (path_col, line_col, new_col) = (column("path")?, column("line")?, column("new")?);
+    let delete_col = column("delete").ok();
+
+    let mut entries = Vec::new();
+    for (idx, record) in reader.records().enumerate() {
+        let row = idx + 2;
+        let record = record.with_context(|| format!("reading CSV row {}", row))?;
+        if record.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+        // A spreadsheet may have reformatted the number, as "1,234" or "12.0".
+        // Skipping the row would silently lose its edit.
+        let (Some(path), Some(line), Some(new)) = (
+            record.get(path_col),
+            record.get(line_col),
+            record.get(new_col),
+        ) else {
+            bail!("CSV row {} needs 'path', 'line' and 'new' cells", row);
+        };
+        let Ok(line) = line.trim().parse() else {
+            bail!(
+                "CSV row {} has '{}' as its line, which isn't a line number",
+                row,
+                line
+            );
+        };
+        let mut entry = Entry::new(path, line, new);
+        if delete_col
+            .and_then(|c| record.get(c))
+            .is_some_and(|d| !d.trim().is_empty())
+        {
+            entry.mark = Mark::Delete;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Read one object per line. A "delete" value of true, or a "new" value of
+/// null, deletes the line.
+pub fn parse_jsonl(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(line)
+            .with_context(|| format!("parsing JSON on line {}", idx + 1))?;
+        let (Some(path), Some(lineno), Some(new)) = (
+            value["path"].as_str(),
+            value["line"].as_u64(),
+            value.get("new").filter(|v| v.is_string() || v.is_null()),
+        ) else {
+            bail!("line {} needs 'path', 'line' and 'new' fields", idx + 1);
+        };
+        let mut entry = Entry::new(path, lineno as usize, new.as_str().unwrap_or_default());
+        if new.is_null() || value["delete"].as_bool() == Some(true) {
+            entry.mark = Mark::Delete;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Pair each line of a raw buffer with its entry in the index. Nothing in the
+/// buffer says where a line came from, so any change to the number or order of
+/// lines makes the pairing unsafe and nothing is applied. A line is deleted by
+/// replacing it with `RAW_DELETE`.
+pub fn parse_raw(new_text: &str, index_text: &str) -> Result<Vec<Entry>> {
+    let index = parse_jsonl(index_text).context("reading the buffer index")?;
+    let lines: Vec<&str> = new_text.lines().collect();
+    if lines.len() != index.len() {
+        bail!(
+            "The buffer has {} lines, but {} were loaded. Lines were added or removed, so they \
+             can't be matched to their files. To delete a line, replace it with {} instead.",
+            lines.len(),
+            index.len(),
+            RAW_DELETE
+        );
+    }
+
+    // A changed line which now holds what another changed line started out as
+    // was most likely moved there
+    let shown = |i: usize| {
+        index[i]
+            .content
+            .strip_suffix('\r')
+            .unwrap_or(&index[i].content)
+    };
+    let changed = |i: usize| lines[i] != shown(i);
+    let moved: Vec<String> = (0..lines.len())
+        .filter(|&i| changed(i))
+        .filter_map(|i| {
+            (0..index.len())
+                .find(|&j| j != i && changed(j) && shown(j) == lines[i])
+                .map(|j| format!("line {} holds the text of line {}", i + 1, j + 1))
+        })
+        .collect();
+    if !moved.is_empty() {
+        bail!(
+            "Lines in the buffer were reordered ({}), so they can't be matched to their \
+             files. Edit lines in place instead of moving them.",
+            moved.join(", ")
+        );
+    }
+
+    Ok(index
+        .into_iter()
+        .zip(lines)
+        .map(|(start, new)| {
+            let mut entry = Entry::new(start.prefix, start.lineno, new);
+            if new == RAW_DELETE && start.content != RAW_DELETE {
+                entry.mark = Mark::Delete;
+            }
+            entry
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino_tempfile::tempdir;
+
+    fn sample() -> (Vec<MatchLine>, BTreeMap<FileAlias, String>) {
+        let alias = FileAlias::from_index(0);
+        let mut lines = vec![
+            MatchLine::new(alias, 3, "let a = \"x, y\";".into()),
+            MatchLine::new(alias, 7, "  b();".into()),
+        ];
+        lines[1].replacement = Some("  c();".into());
+        lines[1].annotation = Some("warning: unused".into());
+        (lines, BTreeMap::from([(alias, "src/a b.rs".to_string())]))
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("buffer.csv");
+        let (lines, paths) = sample();
+        write_csv(&path, &lines, &paths).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text,
+            "path,line,original,new,delete,note\n\
+             src/a b.rs,3,\"let a = \"\"x, y\"\";\",\"let a = \"\"x, y\"\";\",,\n\
+             src/a b.rs,7,  b();,  c();,,warning: unused\n"
+        );
+        assert_eq!(
+            parse_csv(&text).unwrap(),
+            vec![
+                Entry::new("src/a b.rs", 3, "let a = \"x, y\";"),
+                Entry::new("src/a b.rs", 7, "  c();"),
+            ]
+        );
+
+        // As saved by a spreadsheet with a semicolon locale and extra columns
+        let saved = "\u{feff}Path;Line;New;Reviewer\nsrc/a.rs;3;fixed;Ann\n";
+        assert_eq!(
+            parse_csv(saved).unwrap(),
+            vec![Entry::new("src/a.rs", 3, "fixed")]
+        );
+
+        // Only the delete column deletes; an emptied cell empties the line
+        let emptied = "path,line,original,new,delete\na.rs,1,x,,\na.rs,2,x,x, \na.rs,3,x,x,x\n";
+        let marks: Vec<Mark> = parse_csv(emptied)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.mark)
+            .collect();
+        assert_eq!(marks, [Mark::Edit, Mark::Edit, Mark::Delete]);
+
+        // A line number reformatted by a spreadsheet is an error, not a skip
+        let reformatted = "path,line,new\na.rs,3,x\na.rs,\"1,234\",y\n,,\n";
+        let err = parse_csv(reformatted).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("CSV row 3 has '1,234' as its line")
+        );
+        assert!(parse_csv("path,line,new\na.rs,12.0,y\n").is_err());
+        assert!(parse_csv("path,line,new\na.rs,3,x\n,,\n").is_ok());
+    }
+
+    #[test]
+    fn test_jsonl_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("buffer.jsonl");
+        let (lines, paths) = sample();
+        write_jsonl(&path, &lines, &paths).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("{\"path\":\"src/a b.rs\",\"line\":3,\"original\":"));
+        assert!(text.contains(",\"note\":null}\n"));
+        assert!(text.ends_with(",\"note\":\"warning: unused\"}\n"));
+        assert_eq!(
+            parse_jsonl(&text).unwrap(),
+            vec![
+                Entry::new("src/a b.rs", 3, "let a = \"x, y\";"),
+                Entry::new("src/a b.rs", 7, "  c();"),
+            ]
+        );
+        assert!(parse_jsonl("{\"path\": \"a.rs\"}").is_err());
+
+        let deleted = parse_jsonl("{\"path\":\"a.rs\",\"line\":1,\"new\":null}").unwrap();
+        assert_eq!(deleted[0].mark, Mark::Delete);
+        let deleted =
+            parse_jsonl("{\"path\":\"a.rs\",\"line\":1,\"new\":\"x\",\"delete\":true}").unwrap();
+        assert_eq!(deleted[0].mark, Mark::Delete);
+        let emptied = parse_jsonl("{\"path\":\"a.rs\",\"line\":1,\"new\":\"\"}").unwrap();
+        assert_eq!(emptied[0], Entry::new("a.rs", 1, ""));
+        let blank = parse_jsonl("{\"path\":\"a.rs\",\"line\":1,\"new\":\"  \"}").unwrap();
+        assert_eq!(blank[0], Entry::new("a.rs", 1, "  "));
+    }
+
+    #[test]
+    fn test_parse_separator() {
+        assert_eq!(parse_separator("shade").unwrap().chars(), ['▓', '░']);
+        assert_eq!(parse_separator("ascii").unwrap().chars(), ['|', ':']);
+        assert_eq!(parse_separator("»").unwrap().chars(), ['»', '»']);
+        assert!(parse_separator("abc").is_err());
+        assert!(parse_separator("s").is_err());
+        assert!(parse_separator("+").is_err());
+        assert!(parse_separator("| ").is_err());
+    }
+
+    #[test]
+    fn test_raw_structure_checks() {
+        let dir = tempdir().unwrap();
+        let (buffer, index) = (dir.path().join("buffer.txt"), dir.path().join("index"));
+        let alias = FileAlias::from_index(0);
+        let lines: Vec<MatchLine> = ["one", "two", "three"]
+            .iter()
+            .enumerate()
+            .map(|(i, text)| MatchLine::new(alias, i + 1, text.to_string()))
+            .collect();
+        let paths = BTreeMap::from([(alias, "a.txt".to_string())]);
+        write_raw(&buffer, &index, &lines, &paths).unwrap();
+
+        let index = fs::read_to_string(&index).unwrap();
+        assert_eq!(fs::read_to_string(&buffer).unwrap(), "one\ntwo\nthree\n");
+
+        let entries = parse_raw("one\nTWO\nthree\n", &index).unwrap();
+        assert_eq!(entries[1], Entry::new("a.txt", 2, "TWO"));
+        let emptied = parse_raw("one\n\nthree\n", &index).unwrap();
+        assert_eq!(emptied[1], Entry::new("a.txt", 2, ""));
+        let deleted = parse_raw("one\n<<delete>>\nthree\n", &index).unwrap();
+        assert_eq!(deleted[1].mark, Mark::Delete);
+        // Editing a line to match another one isn't a move
+        assert!(parse_raw("one\none\nthree", &index).is_ok());
+
+        let added = parse_raw("one\ntwo\nextra\nthree\n", &index).unwrap_err();
+        assert!(added.to_string().contains("has 4 lines, but 3 were loaded"));
+        let removed = parse_raw("one\nthree\n", &index).unwrap_err();
+        assert!(removed.to_string().contains("has 2 lines"));
+        let swapped = parse_raw("three\ntwo\none\n", &index).unwrap_err();
+        assert!(
+            swapped
+                .to_string()
+                .contains("line 1 holds the text of line 3")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use crate::filters::case_insensitive;
+use crate::search::{LineHit, scan_files};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine};
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// Find every word within --distance edits of the --fuzzy word, noting the
+/// spellings found on each line
+pub fn run_fuzzy_search(
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
+    let word = args
+        .fuzzy
+        .as_ref()
+        .context("Word required for fuzzy search")?;
+    let ignore_case = case_insensitive(word, args);
+    let token_re = Regex::new(r"\w+")?;
+
+    let (files, match_lines) = scan_files(args, filters, |line| {
+        near_misses(&token_re, line, word, args.distance, ignore_case)
+    })?;
+
+    let label = format!("Fuzzy: {} (distance <= {})", word, args.distance);
+    Ok((match_lines, files, label))
+}
+
+/// Words on `line` within `max_distance` edits of `word`, counting adjacent
+/// transpositions as a single edit. Exact matches are left out, since those
+/// are already spelled correctly.
+fn near_misses(
+    token_re: &Regex,
+    line: &str,
+    word: &str,
+    max_distance: usize,
+    ignore_case: bool,
+) -> Vec<LineHit> {
+    let fold = |s: &str| {
+        if ignore_case {
+            s.to_lowercase()
+        } else {
+            s.to_string()
+        }
+    };
+    let word = fold(word);
+    let word_len = word.chars().count();
+
+    token_re
+        .find_iter(line)
+        .filter_map(|tok| {
+            let token = fold(tok.as_str());
+            if token.chars().count().abs_diff(word_len) > max_distance || token == word {
+                return None;
+            }
+            let distance = strsim::osa_distance(&token, &word);
+            (distance <= max_distance).then(|| LineHit {
+                column: tok.start() + 1,
+                note: Some(format!("{} ({})", tok.as_str(), distance)),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_near_misses() {
+        let re = Regex::new(r"\w+").unwrap();
+        let line = "Okapi, Okpai and Okappi met an okapi at the Okapii-Zoo";
+
+        let found: Vec<String> = near_misses(&re, line, "Okapi", 1, false)
+            .into_iter()
+            .filter_map(|m| m.note)
+            .collect();
+        // "Okpai" is a single transposition; "okapi" differs only in case
+        assert_eq!(
+            found,
+            vec!["Okpai (1)", "Okappi (1)", "okapi (1)", "Okapii (1)"]
+        );
+
+        let misses = near_misses(&re, line, "Okapi", 1, true);
+        assert_eq!(misses.len(), 3);
+        assert_eq!(
+            misses[0],
+            LineHit {
+                column: 8,
+                note: Some("Okpai (1)".to_string())
+            }
+        );
+
+        assert!(near_misses(&re, line, "Giraffe", 2, false).is_empty());
+    }
+}
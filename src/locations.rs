@@ -0,0 +1,198 @@
+use anyhow::{Result, bail};
+use regex::Regex;
+
+/// The lines a list entry refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineSpan {
+    /// An inclusive range of 1-indexed lines
+    Range(usize, usize),
+    /// Every line in the file
+    All,
+}
+
+/// One entry from a location list, like `src/main.rs:12:5: unused variable`
+#[derive(Debug, PartialEq)]
+pub struct Location {
+    pub path: String,
+    pub lines: LineSpan,
+    /// Any text after the location, such as a compiler message
+    pub message: Option<String>,
+}
+
+/// Recognizes the location formats printed by common tools
+#[derive(Debug)]
+pub struct LocationParser {
+    patterns: Vec<Regex>,
+}
+
+impl LocationParser {
+    /// Accepts `path:line`, line ranges (`path:10-25` and `path:10+5`, which
+    /// is lines 10 through 15), plus the formats of grep -n (`path:line:text`),
+    /// vimgrep and quickfix lists, gcc and eslint's unix formatter
+    /// (`path:line:col: message`), and MSVC (`path(line,col): message`).
+    pub fn builtin() -> Self {
+        let patterns = [
+            // The path is matched lazily, so the first ":<digits>" that is
+            // followed by ':' or the end of the line is the line number
+            r"^(?P<f>.+?):(?P<l>\d+)(?:(?P<op>[-+])(?P<n>\d+))?(?::(?P<c>\d+))?(?::\s?(?P<m>.*))?$",
+            r"^(?P<f>.+?)\((?P<l>\d+)(?:,(?P<c>\d+))?\)\s*:\s?(?P<m>.*)$",
+        ];
+        Self {
+            patterns: patterns
+                .iter()
+                .map(|p| Regex::new(p).expect("built-in location pattern"))
+                .collect(),
+        }
+    }
+
+    /// Accepts only a location given on the command line: `path:line`, with
+    /// an optional range and column, but no message. Ex: `src/a.rs:10-25`.
+    /// An escaped colon, as in the pattern `port\:80`, isn't one.
+    pub fn argument() -> Self {
+        let pattern = r"^(?P<f>.*?[^\\]):(?P<l>\d+)(?:(?P<op>[-+])(?P<n>\d+))?(?::(?P<c>\d+))?$";
+        Self {
+            patterns: vec![Regex::new(pattern).expect("argument location pattern")],
+        }
+    }
+
+    /// Build a parser from a vim errorformat-like FORMAT. Supported fields are
+    /// %f (file), %l (line), %c (column), %m (message) and %% (a literal %).
+    /// Everything else matches literally. Ex: "%f|%l col %c| %m"
+    pub fn from_format(format: &str) -> Result<Self> {
+        let mut pattern = String::from("^");
+        let mut chars = format.chars();
+        let mut literal = String::new();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let field = match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('f') => r"(?P<f>.+?)",
+                Some('l') => r"(?P<l>\d+)",
+                Some('c') => r"(?P<c>\d+)",
+                Some('m') => r"(?P<m>.*)",
+                Some(other) => bail!("unsupported field %{} in list format", other),
+                None => bail!("list format ends with a lone %"),
+            };
+            pattern.push_str(&regex::escape(&std::mem::take(&mut literal)));
+            pattern.push_str(field);
+        }
+        pattern.push_str(&regex::escape(&literal));
+        pattern.push('$');
+
+        if !pattern.contains("(?P<f>") || !pattern.contains("(?P<l>") {
+            bail!("list format must include both %f and %l");
+        }
+        Ok(Self {
+            patterns: vec![Regex::new(&pattern)?],
+        })
+    }
+
+    pub fn parse(&self, line: &str) -> Option<Location> {
+        self.patterns.iter().find_map(|re| {
+            let cap = re.captures(line)?;
+            let message = cap
+                .name("m")
+                .map(|m| m.as_str().trim())
+                .filter(|m| !m.is_empty());
+
+            let start: usize = cap["l"].parse().ok()?;
+            let end = match (cap.name("op"), cap.name("n")) {
+                (Some(op), Some(n)) => {
+                    let n: usize = n.as_str().parse().ok()?;
+                    if op.as_str() == "+" {
+                        start.checked_add(n)?
+                    } else {
+                        n
+                    }
+                }
+                _ => start,
+            };
+            if end < start {
+                return None;
+            }
+
+            Some(Location {
+                path: cap["f"].to_string(),
+                lines: LineSpan::Range(start, end),
+                message: message.map(str::to_string),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(path: &str, lineno: usize, message: Option<&str>) -> Option<Location> {
+        Some(Location {
+            path: path.to_string(),
+            lines: LineSpan::Range(lineno, lineno),
+            message: message.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn test_builtin_formats() {
+        let p = LocationParser::builtin();
+        assert_eq!(p.parse("src/a.rs:12"), loc("src/a.rs", 12, None));
+        assert_eq!(p.parse("a:b.rs:3"), loc("a:b.rs", 3, None));
+        assert_eq!(
+            p.parse("src/a.rs:12:let x = 1: 2"),
+            loc("src/a.rs", 12, Some("let x = 1: 2"))
+        );
+        assert_eq!(
+            p.parse("src/a.c:12:5: warning: unused variable 'x'"),
+            loc("src/a.c", 12, Some("warning: unused variable 'x'"))
+        );
+        assert_eq!(p.parse("src/a.js:3:1:x"), loc("src/a.js", 3, Some("x")));
+        assert_eq!(
+            p.parse(r"C:\src\a.cpp(40,8): error C2065: 'x': undeclared"),
+            loc(r"C:\src\a.cpp", 40, Some("error C2065: 'x': undeclared"))
+        );
+        assert_eq!(p.parse("In function 'main':"), None);
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        let p = LocationParser::builtin();
+        let span = |line: &str| p.parse(line).map(|l| l.lines);
+
+        assert_eq!(span("a.rs:10-25"), Some(LineSpan::Range(10, 25)));
+        assert_eq!(span("a.rs:10+5"), Some(LineSpan::Range(10, 15)));
+        assert_eq!(span("a.rs:10-25: a message"), Some(LineSpan::Range(10, 25)));
+        assert_eq!(span("a.rs:25-10"), None);
+
+        // Arguments can't carry a message, so text like "key:1 x" isn't one
+        let p = LocationParser::argument();
+        assert_eq!(p.parse("src/a.rs:12"), loc("src/a.rs", 12, None));
+        assert_eq!(p.parse("src/a.rs:12:5"), loc("src/a.rs", 12, None));
+        assert_eq!(
+            p.parse("a.rs:10-25").map(|l| l.lines),
+            Some(LineSpan::Range(10, 25))
+        );
+        assert_eq!(p.parse("src/a.rs:12:let x"), None);
+        assert_eq!(p.parse("TODO"), None);
+        assert_eq!(p.parse(r"port\:8080"), None);
+    }
+
+    #[test]
+    fn test_custom_format() {
+        let p = LocationParser::from_format("%f|%l col %c| %m").unwrap();
+        assert_eq!(
+            p.parse("src/a.rs|7 col 3| fix me"),
+            loc("src/a.rs", 7, Some("fix me"))
+        );
+        assert_eq!(p.parse("src/a.rs:7"), None);
+
+        assert!(LocationParser::from_format("%f:%x").is_err());
+        assert!(LocationParser::from_format("%m").is_err());
+    }
+}
@@ -1,30 +1,124 @@
+mod case_variants;
+mod code_search;
+mod diagnostics;
 mod editor;
+mod escape;
 mod file_alias;
 mod file_loader;
+mod filters;
+mod formats;
+mod fuzzy;
+mod locations;
+mod normalize;
+mod patch;
+mod prefix;
+mod sarif;
 mod search;
+mod syntax;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use camino::Utf8PathBuf;
-use clap::{ArgGroup, Parser};
+use clap::error::ErrorKind;
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser};
 use file_alias::FileAlias;
+use filters::{Filters, ProximitySpec};
+use formats::{BufferFormat, Separator};
+use locations::LocationParser;
+use prefix::PrefixStyle;
+use syntax::SyntaxKind;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
-// Create a group that requires exactly one of 'pattern' or 'file'
+// Group the inputs: a 'pattern', or one of the search and list options. One is required unless
+// --syntax is given, which clap can't express, so main checks for it.
+// With --case-variants or --fuzzy, a positional 'pattern' is treated as a path.
 #[command(group(
     ArgGroup::new("input")
-        .required(true)
-        .args(["pattern", "file"]),
+        .multiple(true)
+        .args(["pattern", "file", "case_variants", "fuzzy", "sarif", "diagnostics", "from_diff", "code_search"]),
 ))]
 pub struct Args {
-    /// Rust regex pattern (passed to ripgrep)
-    #[arg(required_unless_present = "file")]
+    /// Rust regex pattern (passed to ripgrep). With --file or locations, only listed lines matching
+    /// it are kept. A location like `src/a.rs:12` is loaded instead of searched for
     pub pattern: Option<String>,
 
-    /// Path to a file of path:line entries, one per line. Pass '-' to read from stdin
-    #[arg(short, long, conflicts_with = "pattern", value_name = "FILE_PATH")]
-    pub file: Option<Utf8PathBuf>,
+    /// Path to a file of path:line entries, one per line. Pass '-' to read from stdin. Output from
+    /// grep -n, vimgrep, gcc, eslint (unix) and MSVC also works; messages are shown in the buffer.
+    /// May be repeated
+    #[arg(short, long, value_name = "FILE_PATH")]
+    pub file: Vec<Utf8PathBuf>,
 
+    /// Locations from the positional arguments, like `src/a.rs:12`, taken out of
+    /// PATTERN and PATHS by `Args::resolve_positionals`
+    #[arg(skip)]
+    pub locations: Vec<String>,
+
+    /// Path to a SARIF log. Each result location is loaded, with its rule ID and message shown in
+    /// the buffer. Pass '-' to read from stdin
+    #[arg(long, value_name = "FILE_PATH", conflicts_with_all = ["pattern", "file", "case_variants", "fuzzy"])]
+    pub sarif: Option<Utf8PathBuf>,
+
+    /// Path to `cargo check --message-format=json` or `rustc --error-format=json` output. The
+    /// primary span of each diagnostic is loaded, with its lint and message shown in the buffer.
+    /// Pass '-' to read from stdin
+    #[arg(long, value_name = "FILE_PATH", conflicts_with_all = ["pattern", "file", "sarif", "case_variants", "fuzzy"])]
+    pub diagnostics: Option<Utf8PathBuf>,
+
+    /// Pre-fill lines from --diagnostics with rustc's machine-applicable suggestions
+    #[arg(long, requires = "diagnostics")]
+    pub apply_suggestions: bool,
+
+    /// Path to a unified diff, like `git diff` output or a patch. Every line it adds which is still
+    /// in the working tree is loaded. Pass '-' to read from stdin
+    #[arg(long, value_name = "FILE_PATH", conflicts_with_all = ["pattern", "file", "sarif", "diagnostics", "case_variants", "fuzzy"])]
+    pub from_diff: Option<Utf8PathBuf>,
+
+    /// Path to a VS Code Search Editor (.code-search) file. Each result line is loaded; context
+    /// lines are skipped
+    #[arg(long, value_name = "FILE_PATH", conflicts_with_all = ["pattern", "file", "sarif", "diagnostics", "from_diff", "case_variants", "fuzzy"])]
+    pub code_search: Option<Utf8PathBuf>,
+
+    /// Save the matches to a VS Code Search Editor (.code-search) file instead of editing them
+    #[arg(long, value_name = "FILE_PATH")]
+    pub save_code_search: Option<Utf8PathBuf>,
+
+    /// When some list entries can't be loaded, continue with the rest without asking
+    #[arg(long)]
+    pub skip_invalid: bool,
+
+    /// Read --file entries in this errorformat-like layout, with fields %f (file), %l (line),
+    /// %c (column), %m (message) and %% (literal). Ex: '%f|%l col %c| %m'
+    #[arg(long, value_name = "FORMAT", requires = "file")]
+    pub list_format: Option<String>,
+
+    /// Search for every common casing of WORD: snake_case, camelCase, PascalCase,
+    /// SCREAMING_SNAKE_CASE and kebab-case
+    #[arg(long, value_name = "WORD", conflicts_with = "file")]
+    pub case_variants: Option<String>,
+
+    /// Find words within --distance edits of WORD, such as misspellings of a product name
+    #[arg(long, value_name = "WORD", conflicts_with_all = ["file", "case_variants"])]
+    pub fuzzy: Option<String>,
+
+    /// Maximum number of single-character edits for --fuzzy. A transposition counts as one edit
+    #[arg(long, value_name = "N", default_value = "2", requires = "fuzzy")]
+    pub distance: usize,
+
+    /// Match the pattern regardless of Unicode normalization form (NFC/NFD). Edits are written
+    /// back in each file's original form
+    #[arg(long, conflicts_with_all = ["file", "case_variants", "fuzzy"])]
+    pub normalize: bool,
+
+    /// Like --normalize, but also ignore diacritics, so "Cafe" matches "Café"
+    #[arg(long, conflicts_with_all = ["file", "case_variants", "fuzzy"])]
+    pub ignore_diacritics: bool,
+
+    /// Pre-fill each --case-variants line with NAME, cased like the variant it replaces
+    #[arg(short, long, value_name = "NAME", requires = "case_variants")]
+    pub replace: Option<String>,
+
+    /// Files and directories to search. Locations like `src/a.rs:12` or `src/a.rs:10-25` are loaded
+    /// instead, as are paths given with --file
     #[arg(value_name = "PATHS", num_args = 0..)]
     pub paths: Vec<Utf8PathBuf>,
 
@@ -32,6 +126,32 @@ pub struct Args {
     #[arg(short = 'd', long, value_name = "COMMAND")]
     pub editor: Option<String>,
 
+    /// Layout of the editing buffer: shaded text, CSV with path, line, original, new, delete and
+    /// note columns for spreadsheets, JSON lines with the same fields, or raw line contents with no
+    /// prefixes
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    pub format: BufferFormat,
+
+    /// Show tabs, trailing spaces, control and invisible characters in the text buffer as escapes
+    /// like \t, \s and \u{200B}, so they can be seen and edited. Backslashes are doubled
+    #[arg(long)]
+    pub escape: bool,
+
+    /// The characters between each line number and its content, alternating by file: 'shade'
+    /// (▓░), 'ascii' (|:), or one or two characters of your own
+    #[arg(long, value_name = "CHARS", default_value = "shade", value_parser = formats::parse_separator)]
+    pub separator: Separator,
+
+    /// Print a syntax definition for the buffer, which dims the gutter and highlights content in its
+    /// source language, then exit. Pass the same --separator as the buffers are written with
+    #[arg(long, value_enum, value_name = "EDITOR", conflicts_with = "input")]
+    pub syntax: Option<SyntaxKind>,
+
+    /// How text buffer lines name their file: letter aliases (the default), a shortened relative
+    /// path, the file name, or the full relative path
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub prefix: Option<PrefixStyle>,
+
     /// Truncate matches to prevent an overlarge virtual buffer
     #[arg(short, long, default_value = "1000")]
     pub max_count: usize,
@@ -40,10 +160,40 @@ pub struct Args {
     #[arg(short, long, value_name = "PATTERN")]
     pub exclude: Vec<String>,
 
-    /// Run a case-insensitive search. Passed though to ripgrep. Also applies to --exclude pattern
-    #[arg(short, long)]
+    /// Lines matching the initial pattern are only kept if they also match this one
+    #[arg(long, value_name = "PATTERN")]
+    pub require: Vec<String>,
+
+    /// Keep only lines with a match for PATTERN within N lines, above or below. Ex: '// SAFETY:3'
+    #[arg(long, value_name = "PATTERN:N", value_parser = filters::parse_proximity)]
+    pub near: Vec<ProximitySpec>,
+
+    /// Drop lines with a match for PATTERN within N lines, above or below. Ex: 'if DEBUG:5'
+    #[arg(long, value_name = "PATTERN:N", value_parser = filters::parse_proximity)]
+    pub not_near: Vec<ProximitySpec>,
+
+    /// Run a case-insensitive search. Passed though to ripgrep. Like the other pattern options, it
+    /// also applies to --exclude, --require, --near and --not-near patterns
+    #[arg(short, long, overrides_with = "smart_case")]
     pub ignore_case: bool,
 
+    /// Search case-insensitively if the pattern is all lowercase, and case-sensitively otherwise.
+    /// As in ripgrep, whichever of --ignore-case and --smart-case comes last wins
+    #[arg(short = 'S', long, overrides_with = "ignore_case")]
+    pub smart_case: bool,
+
+    /// Treat all patterns as literal strings instead of regexes
+    #[arg(short = 'F', long, conflicts_with = "case_variants")]
+    pub fixed_strings: bool,
+
+    /// Only match patterns surrounded by word boundaries
+    #[arg(long)]
+    pub word_regexp: bool,
+
+    /// Allow patterns to match across lines. Proximity patterns match across the whole window
+    #[arg(short = 'U', long)]
+    pub multiline: bool,
+
     /// Prefixed to paths found in --file and paths provided as positional args
     #[arg(short, long, value_name = "PATH")]
     pub working_directory: Option<Utf8PathBuf>,
@@ -72,41 +222,149 @@ pub struct MatchLine {
     pub alias: FileAlias,
     pub lineno: usize,
     pub original_content: String,
+    /// Written to the buffer in place of `original_content`, as a suggested edit
+    pub replacement: Option<String>,
+    /// Read-only note shown as a comment above the line in the buffer
+    pub annotation: Option<String>,
+}
+
+impl Args {
+    /// Whether lines come from --file lists and locations rather than a search
+    pub fn list_mode(&self) -> bool {
+        !self.file.is_empty() || !self.locations.is_empty()
+    }
+
+    /// Sort out what the positional arguments mean. With --case-variants or
+    /// --fuzzy, PATTERN is a path to search. Otherwise, if any of them is a
+    /// location like `src/a.rs:12`, all the paths are loaded as locations,
+    /// with whole files for those without a line, and PATTERN filters them
+    /// unless it's a location itself. The same goes for paths given with --file.
+    pub fn resolve_positionals(&mut self) {
+        if self.case_variants.is_some() || self.fuzzy.is_some() {
+            if let Some(path) = self.pattern.take() {
+                self.paths.insert(0, path.into());
+            }
+            return;
+        }
+        let importing = self.sarif.is_some()
+            || self.diagnostics.is_some()
+            || self.from_diff.is_some()
+            || self.code_search.is_some()
+            || self.normalize
+            || self.ignore_diacritics;
+        if importing {
+            return;
+        }
+
+        let parser = LocationParser::argument();
+        if let Some(pattern) = self.pattern.take_if(|p| parser.parse(p).is_some()) {
+            self.paths.insert(0, pattern.into());
+        }
+        if !self.file.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|p| parser.parse(p.as_str()).is_some())
+        {
+            self.locations = self.paths.drain(..).map(Utf8PathBuf::into_string).collect();
+        }
+    }
+}
+
+impl MatchLine {
+    pub fn new(alias: FileAlias, lineno: usize, original_content: String) -> Self {
+        Self {
+            alias,
+            lineno,
+            original_content,
+            replacement: None,
+            annotation: None,
+        }
+    }
 }
 
 fn main() -> Result<()> {
     env_logger::init();
-    let args = Args::parse();
+    let mut command = Args::command();
+    let matches = command.get_matches_mut();
+    let mut args = Args::from_arg_matches(&matches)?;
+    if let Some(kind) = args.syntax {
+        print!("{}", syntax::definition(kind, args.separator));
+        return Ok(());
+    }
+    if !matches.contains_id("input") {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "a PATTERN, location, or an input such as --file or --sarif is required",
+            )
+            .exit();
+    }
 
-    let (match_lines, files, label) = if let Some(ref list_path) = args.file {
-        if list_path == "-" {
-            file_loader::load_from_stdin(&args)?
-        } else {
-            file_loader::load_from_list(list_path, &args)?
-        }
+    if args.escape && args.format != BufferFormat::Text {
+        bail!("--escape only applies to the text buffer format");
+    }
+    if args.prefix.is_some() && args.format != BufferFormat::Text {
+        bail!("--prefix only applies to the text buffer format; other formats name files by path");
+    }
+
+    args.resolve_positionals();
+    let filters = Filters::from_args(&args)?;
+
+    let (match_lines, mut files, label) = if args.list_mode() {
+        file_loader::load_from_lists(&args, &filters)?
+    } else if let Some(ref search_path) = args.code_search {
+        code_search::load_from_code_search(search_path, &args, &filters)?
+    } else if let Some(ref diff_path) = args.from_diff {
+        patch::load_from_diff(diff_path, &args, &filters)?
+    } else if let Some(ref input) = args.diagnostics {
+        diagnostics::load_from_diagnostics(input, &args, &filters)?
+    } else if let Some(ref sarif_path) = args.sarif {
+        sarif::load_from_sarif(sarif_path, &args, &filters)?
+    } else if args.fuzzy.is_some() {
+        fuzzy::run_fuzzy_search(&args, &filters)?
+    } else if args.normalize || args.ignore_diacritics {
+        normalize::run_normalized_search(&args, &filters)?
     } else {
-        search::run_ripgrep_search(&args)?
+        search::run_ripgrep_search(&args, &filters)?
     };
+    let match_lines = filters.apply_proximity(match_lines, &mut files, args.max_count);
 
     if match_lines.is_empty() {
         println!("No matches found.");
         return Ok(());
     }
 
+    if let Some(ref out_path) = args.save_code_search {
+        return code_search::save_code_search(out_path, &args, &match_lines, &files);
+    }
+
     editor::run_editor_session(&args, &label, match_lines, files)?;
 
     Ok(())
 }
 
+/// Every alias in order: A-Z, then AA-ZZ, then AAA-ZZZ, and so on
 pub fn alias_iter() -> impl Iterator<Item = FileAlias> {
-    let alphabet = 'A'..='Z';
-    let singles = alphabet.clone().map(|c| FileAlias::new(&[c]));
-    let doubles = itertools::iproduct!(alphabet.clone(), alphabet.clone())
-        .map(|(c1, c2)| FileAlias::new(&[c1, c2]));
-    let triples = itertools::iproduct!(alphabet.clone(), alphabet.clone(), alphabet.clone())
-        .map(|(c1, c2, c3)| FileAlias::new(&[c1, c2, c3]));
-
-    singles.chain(doubles).chain(triples)
+    (0..).map(FileAlias::from_index)
+}
+
+/// Files with the given paths and contents, as if loaded, aliased A, B, ... in order
+#[cfg(test)]
+pub fn test_files(files: &[(&str, &str)]) -> std::collections::BTreeMap<FileAlias, FileInfo> {
+    alias_iter()
+        .zip(files)
+        .map(|(alias, (path, content))| {
+            let info = FileInfo {
+                path: Utf8PathBuf::from(path),
+                full_path: Utf8PathBuf::from(path),
+                alias,
+                original_content: content.to_string(),
+                original_mtime: std::time::SystemTime::UNIX_EPOCH,
+            };
+            (alias, info)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -130,5 +388,15 @@ mod tests {
         // Should eventually hit triples
         let mut triples = alias_iter().skip(26 + (26 * 26));
         assert_eq!(triples.next().unwrap().to_string(), "AAA");
+
+        // And keep going past ZZZ
+        let mut quads = alias_iter().skip(26 + (26 * 26) + (26 * 26 * 26) - 1);
+        let zzz = quads.next().unwrap();
+        let aaaa = quads.next().unwrap();
+        assert_eq!(zzz.to_string(), "ZZZ");
+        assert_eq!(aaaa.to_string(), "AAAA");
+        assert!(zzz < aaaa);
+        assert_eq!("AAAA".parse::<FileAlias>().unwrap(), aaaa);
+        assert!("A1".parse::<FileAlias>().is_err());
     }
 }
@@ -0,0 +1,202 @@
+use crate::filters::FilterPattern;
+use crate::search::{LineHit, scan_files};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine};
+use anyhow::{Context, Result};
+use similar::{Algorithm, DiffOp, capture_diff_slices};
+use std::collections::BTreeMap;
+use unicode_normalization::char::{canonical_combining_class, is_combining_mark};
+use unicode_normalization::{UnicodeNormalization, is_nfc, is_nfd};
+
+/// The Unicode normalization form a file was written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    Nfc,
+    Nfd,
+    /// Neither form throughout, so new text is written as typed
+    Mixed,
+}
+
+impl Form {
+    pub fn detect(text: &str) -> Self {
+        if is_nfc(text) {
+            Form::Nfc
+        } else if is_nfd(text) {
+            Form::Nfd
+        } else {
+            Form::Mixed
+        }
+    }
+
+    fn apply(self, text: &str) -> String {
+        match self {
+            Form::Nfc => text.nfc().collect(),
+            Form::Nfd => text.nfd().collect(),
+            Form::Mixed => text.to_string(),
+        }
+    }
+}
+
+/// Fold text so that canonically equivalent strings compare equal. With
+/// `strip_diacritics`, combining marks are removed too, so "Café" == "Cafe".
+pub fn fold(text: &str, strip_diacritics: bool) -> String {
+    if strip_diacritics {
+        text.nfd()
+            .filter(|&c| !is_combining_mark(c))
+            .nfc()
+            .collect()
+    } else {
+        text.nfc().collect()
+    }
+}
+
+/// Search for the pattern in folded text, so that it matches whatever
+/// normalization form or accents the files use
+pub fn run_normalized_search(
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
+    let pattern = args
+        .pattern
+        .as_ref()
+        .context("Pattern required for search")?;
+    let matcher = FilterPattern::new(pattern, args)?;
+
+    let (files, match_lines) = scan_files(args, filters, |line| {
+        matcher
+            .match_starts(line)
+            .into_iter()
+            .map(|start| LineHit {
+                column: original_offset(line, start, args.ignore_diacritics) + 1,
+                note: None,
+            })
+            .collect()
+    })?;
+
+    let mode = if args.ignore_diacritics {
+        "normalized, ignoring diacritics"
+    } else {
+        "normalized"
+    };
+    Ok((match_lines, files, format!("Regex ({}): {}", mode, pattern)))
+}
+
+/// The byte offset in `line` of the character which folds to `folded_start`
+/// in the folded line, so that columns refer to the file's own text
+fn original_offset(line: &str, folded_start: usize, strip_diacritics: bool) -> usize {
+    let bounds: Vec<usize> = line
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([line.len()])
+        .collect();
+    // The last character boundary whose folded prefix doesn't reach past the match
+    let after =
+        bounds.partition_point(|&i| fold(&line[..i], strip_diacritics).len() <= folded_start);
+    bounds[after - 1]
+}
+
+/// Split text into a base character followed by its combining characters.
+/// Each segment normalizes independently of its neighbours.
+fn segments(text: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if i > start && canonical_combining_class(c) == 0 {
+            result.push(&text[start..i]);
+            start = i;
+        }
+    }
+    if start < text.len() {
+        result.push(&text[start..]);
+    }
+    result
+}
+
+/// Merge an edited line back into the original's bytes. Segments which are
+/// canonically unchanged keep their exact original bytes, whatever form the
+/// editor saved them in. New text is written in the file's form.
+pub fn restore_form(original: &str, edited: &str, form: Form) -> String {
+    let orig_segs = segments(original);
+    let new_segs = segments(edited);
+    let orig_keys: Vec<String> = orig_segs.iter().map(|s| s.nfc().collect()).collect();
+    let new_keys: Vec<String> = new_segs.iter().map(|s| s.nfc().collect()).collect();
+
+    let mut result = String::with_capacity(edited.len());
+    for op in capture_diff_slices(Algorithm::Myers, &orig_keys, &new_keys) {
+        match op {
+            DiffOp::Equal { old_index, len, .. } => {
+                result.extend(orig_segs[old_index..old_index + len].iter().copied());
+            }
+            DiffOp::Delete { .. } => {}
+            DiffOp::Insert {
+                new_index, new_len, ..
+            }
+            | DiffOp::Replace {
+                new_index, new_len, ..
+            } => {
+                let inserted = new_segs[new_index..new_index + new_len].concat();
+                result.push_str(&form.apply(&inserted));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    const CAFE_NFC: &str = "Caf\u{e9}";
+    const CAFE_NFD: &str = "Cafe\u{301}";
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold(CAFE_NFD, false), CAFE_NFC);
+        assert_eq!(fold(CAFE_NFC, true), "Cafe");
+        assert_eq!(fold(CAFE_NFD, true), "Cafe");
+    }
+
+    #[test]
+    fn test_original_offset() {
+        // "x" is the 6th character either way, but at byte 7 in the NFD line
+        let line = format!("{} x", CAFE_NFD);
+        let folded = fold(&line, true);
+        assert_eq!(folded.find('x'), Some(5));
+        assert_eq!(original_offset(&line, 5, true), 7);
+        assert_eq!(original_offset(&line, 3, true), 3);
+
+        let folded = fold(&line, false);
+        assert_eq!(folded.find('x'), Some(6));
+        assert_eq!(original_offset(&line, 6, false), 7);
+        assert_eq!(original_offset(&line, 3, false), 3);
+    }
+
+    #[test]
+    fn test_folded_filters() {
+        let args = Args::parse_from(["okapi", "menu", "--ignore-diacritics", "--exclude", "Cafe"]);
+        let filters = Filters::from_args(&args).unwrap();
+        assert!(!filters.keeps_content(&format!("{} menu", CAFE_NFD)));
+        assert!(filters.keeps_content("tea menu"));
+    }
+
+    #[test]
+    fn test_restore_form() {
+        // The editor re-saved the whole NFD line as NFC, but only "au" was typed
+        let original = format!("{} noir", CAFE_NFD);
+        let edited = format!("{} au noir", CAFE_NFC);
+        let restored = restore_form(&original, &edited, Form::Nfd);
+        assert_eq!(restored, format!("{} au noir", CAFE_NFD));
+
+        // New accented text takes on the file's form
+        let restored = restore_form("tea", &format!("tea {}", CAFE_NFC), Form::Nfd);
+        assert_eq!(restored, format!("tea {}", CAFE_NFD));
+
+        // Mixed files keep both the original bytes and the new text as typed
+        let original = format!("{} {}", CAFE_NFC, CAFE_NFD);
+        let edited = format!("{} {}!", CAFE_NFD, CAFE_NFC);
+        assert_eq!(
+            restore_form(&original, &edited, Form::Mixed),
+            format!("{}!", original)
+        );
+    }
+}
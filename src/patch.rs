@@ -0,0 +1,191 @@
+use crate::file_loader::{
+    get_absolute_base, load_locations, read_input, resolve_path, retain_matched,
+};
+use crate::locations::{LineSpan, Location};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use log::debug;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+
+/// A line added by a diff, with its line number in the new version of the file
+#[derive(Debug, PartialEq)]
+struct AddedLine {
+    path: String,
+    lineno: usize,
+    content: String,
+}
+
+/// Load the lines a unified diff adds, if they're still in the working tree
+pub fn load_from_diff(
+    diff_path: &Utf8PathBuf,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
+    let content = read_input(diff_path)?;
+    let added = parse_diff(&content)?;
+    let base = get_absolute_base(args)?;
+
+    let locations = added
+        .iter()
+        .map(|a| Location {
+            path: a.path.clone(),
+            lines: LineSpan::Range(a.lineno, a.lineno),
+            message: None,
+        })
+        .collect();
+    let expected: HashMap<(Utf8PathBuf, usize), &str> = added
+        .iter()
+        .map(|a| ((resolve_path(&base, &a.path), a.lineno), a.content.as_str()))
+        .collect();
+
+    let (matches, mut files) = load_locations(locations, args, filters)?;
+    let total = matches.len();
+    let matches: Vec<MatchLine> = matches
+        .into_iter()
+        .filter(|m| {
+            let key = (files[&m.alias].full_path.clone(), m.lineno);
+            // Diffs are often saved with their own line endings
+            expected.get(&key).is_some_and(|e| {
+                e.trim_end_matches('\r') == m.original_content.trim_end_matches('\r')
+            })
+        })
+        .collect();
+
+    retain_matched(&mut files, &matches);
+    if matches.len() < total {
+        eprintln!(
+            "Skipped {} added line(s) which no longer match the working tree",
+            total - matches.len()
+        );
+    }
+
+    let label = if diff_path == "-" {
+        "Diff: STDIN".to_string()
+    } else {
+        format!("Diff: {}", diff_path)
+    };
+    Ok((matches, files, label))
+}
+
+fn parse_diff(content: &str) -> Result<Vec<AddedLine>> {
+    let hunk_re = Regex::new(r"^@@ -\d+(?:,(\d+))? \+(\d+)(?:,(\d+))? @@")?;
+    let mut added = Vec::new();
+    let mut old_path: Option<&str> = None;
+    let mut path: Option<String> = None;
+    let mut lineno = 0;
+    // Lines remaining in the current hunk. Counting them keeps removed lines
+    // like "-- comment" from being read as file headers.
+    let (mut old_left, mut new_left) = (0usize, 0usize);
+
+    for line in content.lines() {
+        if old_left > 0 || new_left > 0 {
+            match line.chars().next() {
+                Some('+') => {
+                    if let Some(ref current) = path {
+                        added.push(AddedLine {
+                            path: current.clone(),
+                            lineno,
+                            content: line[1..].to_string(),
+                        });
+                    }
+                    lineno += 1;
+                    new_left = new_left.saturating_sub(1);
+                }
+                Some('-') => old_left = old_left.saturating_sub(1),
+                // An empty context line comes from tools which trim trailing whitespace
+                Some(' ') | None => {
+                    lineno += 1;
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                }
+                // "\ No newline at end of file"
+                Some('\\') => {}
+                Some(_) => {
+                    debug!("Hunk ended early at: {}", line);
+                    (old_left, new_left) = (0, 0);
+                }
+            }
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("--- ") {
+            old_path = Some(strip_timestamp(rest));
+        } else if let Some(rest) = line.strip_prefix("+++ ") {
+            let new_path = strip_timestamp(rest);
+            // Deleted files have nothing left to edit
+            path = (new_path != "/dev/null").then(|| {
+                let git_prefixes = old_path.is_none_or(|p| p.starts_with("a/") || p == "/dev/null");
+                match new_path.strip_prefix("b/") {
+                    Some(stripped) if git_prefixes => stripped.to_string(),
+                    _ => new_path.to_string(),
+                }
+            });
+        } else if let Some(cap) = hunk_re.captures(line) {
+            let count = |i: usize| cap.get(i).map_or(Ok(1), |m| m.as_str().parse());
+            old_left = count(1).context("parsing hunk header")?;
+            lineno = cap[2].parse().context("parsing hunk header")?;
+            new_left = count(3).context("parsing hunk header")?;
+        }
+    }
+    Ok(added)
+}
+
+/// Plain `diff -u` follows the path with a tab and a timestamp
+fn strip_timestamp(path: &str) -> &str {
+    path.split('\t').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diff() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 83db48f..bf269f4 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,4 +1,5 @@
+ use std::fs;
+-use std::io;
+--- not a header
++use std::io::{self, Write};
++
++++ not a header either
+ fn main() {
+@@ -10,2 +11,3 @@ fn helper() {
+     let x = 1;
++    let y = 2;
+ }
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+--- notes.txt\t2024-01-01 10:00:00
++++ notes.txt\t2024-01-02 10:00:00
+@@ -0,0 +1 @@
++hello
+";
+        let added = parse_diff(diff).unwrap();
+        let found: Vec<(&str, usize, &str)> = added
+            .iter()
+            .map(|a| (a.path.as_str(), a.lineno, a.content.as_str()))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("src/lib.rs", 2, "use std::io::{self, Write};"),
+                ("src/lib.rs", 3, ""),
+                ("src/lib.rs", 4, "++ not a header either"),
+                ("src/lib.rs", 12, "    let y = 2;"),
+                ("notes.txt", 1, "hello"),
+            ]
+        );
+    }
+}
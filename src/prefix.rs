@@ -0,0 +1,293 @@
+use crate::{FileAlias, FileInfo};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How each buffer line names its file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PrefixStyle {
+    /// Letter aliases, listed in the footer: A, B, ... AA
+    #[default]
+    Letters,
+    /// The relative path with each directory cut short: s/e/mod.rs
+    Short,
+    /// The file name, plus ~2, ~3 and so on when names repeat: mod.rs~2
+    Basename,
+    /// The full relative path: src/editor/mod.rs
+    Path,
+    /// Letters hashed from the path in its repository, the same in every session: KQXMTB
+    Hash,
+}
+
+impl PrefixStyle {
+    /// Letters are right-aligned, like numbers; paths read better left-aligned
+    pub fn right_aligned(self) -> bool {
+        matches!(self, PrefixStyle::Letters | PrefixStyle::Hash)
+    }
+}
+
+/// The letters in a hashed prefix. Six keep collisions rare (about one in
+/// 600 sessions with a thousand files) while staying short enough to read.
+const HASH_LETTERS: usize = 6;
+
+/// The buffer prefix for every file. Each is unique, so it maps back to the file.
+pub fn assign(
+    files: &BTreeMap<FileAlias, FileInfo>,
+    style: PrefixStyle,
+    base: &Utf8Path,
+) -> BTreeMap<FileAlias, String> {
+    let relative = || {
+        files
+            .values()
+            .map(|f| (f.alias, relative_path(&f.full_path, base)))
+    };
+    match style {
+        PrefixStyle::Letters => files.keys().map(|a| (*a, a.val())).collect(),
+        PrefixStyle::Path => relative().map(|(a, p)| (a, p.to_string())).collect(),
+        PrefixStyle::Short => unique_prefixes(relative().collect(), 1, |p, len| abbreviate(p, len)),
+        PrefixStyle::Basename => basenames(relative().collect()),
+        PrefixStyle::Hash => {
+            let keys = files
+                .values()
+                .map(|f| (f.alias, hash_key(&f.full_path)))
+                .collect();
+            hash_prefixes(keys, hash_letters)
+        }
+    }
+}
+
+/// `full_path` relative to `base` if it's inside it, without any leading "./"
+pub fn relative_path<'a>(full_path: &'a Utf8Path, base: &Utf8Path) -> &'a Utf8Path {
+    let path = full_path.strip_prefix(base).unwrap_or(full_path);
+    path.strip_prefix(".").unwrap_or(path)
+}
+
+/// Render each item at increasing lengths, starting from `len`, until its
+/// prefix is unique. Items which can't grow any further are told apart with
+/// "~2", "~3" and so on.
+fn unique_prefixes<T>(
+    mut pending: Vec<(FileAlias, T)>,
+    mut len: usize,
+    render: impl Fn(&T, usize) -> String,
+) -> BTreeMap<FileAlias, String> {
+    let mut prefixes = BTreeMap::new();
+
+    while !pending.is_empty() {
+        let rendered: Vec<String> = pending.iter().map(|(_, t)| render(t, len)).collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for r in &rendered {
+            *counts.entry(r.as_str()).or_default() += 1;
+        }
+
+        let mut repeats: HashMap<&str, usize> = HashMap::new();
+        let mut still_pending = Vec::new();
+        for ((alias, item), prefix) in pending.into_iter().zip(&rendered) {
+            if counts[prefix.as_str()] == 1 {
+                prefixes.insert(alias, prefix.clone());
+            } else if render(&item, len + 1) == *prefix {
+                let n = repeats.entry(prefix.as_str()).or_default();
+                *n += 1;
+                let prefix = match *n {
+                    1 => prefix.clone(),
+                    n => format!("{}~{}", prefix, n),
+                };
+                prefixes.insert(alias, prefix);
+            } else {
+                still_pending.push((alias, item));
+            }
+        }
+        pending = still_pending;
+        len += 1;
+    }
+    prefixes
+}
+
+/// The path a file is hashed by: relative to the root of the git repository
+/// holding it, so the prefix doesn't depend on where okapi is run from, or
+/// its canonical path when it's outside a repository.
+fn hash_key(full_path: &Utf8Path) -> String {
+    let path = full_path
+        .canonicalize_utf8()
+        .unwrap_or_else(|_| full_path.to_path_buf());
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(&path)
+        .to_string()
+}
+
+/// Give each file the code for its key. Keys are taken in sorted order and a
+/// code which is already taken is retried with the next attempt number, so a
+/// file's prefix depends only on the files it collides with, never on the
+/// order or aliases of the others in the session.
+fn hash_prefixes(
+    mut keys: Vec<(FileAlias, String)>,
+    code: impl Fn(&str, u32) -> String,
+) -> BTreeMap<FileAlias, String> {
+    keys.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut taken = HashSet::new();
+    let mut prefixes = BTreeMap::new();
+
+    for (alias, key) in keys {
+        let prefix = (0..)
+            .map(|attempt| code(&key, attempt))
+            .find(|c| !taken.contains(c))
+            .expect("some attempt is free");
+        taken.insert(prefix.clone());
+        prefixes.insert(alias, prefix);
+    }
+    prefixes
+}
+
+/// The 64-bit FNV-1a hash of a key, written as `HASH_LETTERS` letters. FNV is
+/// used because, unlike std's hasher, it's guaranteed not to change between
+/// builds. Attempts after the first hash the key with the attempt number added.
+fn hash_letters(key: &str, attempt: u32) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let suffix = (attempt > 0).then(|| format!("\0{}", attempt));
+    for byte in key.bytes().chain(suffix.iter().flat_map(|s| s.bytes())) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (0..HASH_LETTERS)
+        .map(|_| {
+            let letter = (b'A' + (hash % 26) as u8) as char;
+            hash /= 26;
+            letter
+        })
+        .collect()
+}
+
+/// Cut each directory in `path` to `len` characters, not counting a leading '.'.
+/// This starts at a letter per directory, lengthening them only as far as
+/// needed to tell apart files which would otherwise look the same.
+fn abbreviate(path: &Utf8Path, len: usize) -> String {
+    let components: Vec<Utf8Component> = path.components().collect();
+    let mut short = Utf8PathBuf::new();
+
+    for (i, c) in components.iter().enumerate() {
+        match c {
+            Utf8Component::Normal(dir) if i + 1 < components.len() => {
+                let keep = len + usize::from(dir.starts_with('.'));
+                short.push(dir.chars().take(keep).collect::<String>());
+            }
+            other => short.push(other.as_str()),
+        }
+    }
+    short.into_string()
+}
+
+/// File names, with a "~N" suffix on each repeat of a name already used
+fn basenames(paths: Vec<(FileAlias, &Utf8Path)>) -> BTreeMap<FileAlias, String> {
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut prefixes = BTreeMap::new();
+
+    for (alias, path) in paths {
+        let name = path.file_name().unwrap_or(path.as_str());
+        let mut prefix = name.to_string();
+        let mut n = 1;
+        while taken.contains(&prefix) {
+            n += 1;
+            prefix = format!("{}~{}", name, n);
+        }
+        taken.insert(prefix.clone());
+        prefixes.insert(alias, prefix);
+    }
+    prefixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alias_iter;
+    use camino_tempfile::tempdir;
+    use std::fs;
+
+    fn prefixes(style: PrefixStyle) -> Vec<String> {
+        let paths = [
+            "/work/src/editor/mod.rs",
+            "/work/src/search/mod.rs",
+            "/work/spec/editor/mod.rs",
+            "/work/.github/ci.yml",
+            "./README.md",
+        ];
+        let paths: Vec<(FileAlias, &Utf8Path)> =
+            alias_iter().zip(paths.iter().map(Utf8Path::new)).collect();
+        let base = Utf8Path::new("/work");
+        let relative = paths.iter().map(|&(a, p)| (a, relative_path(p, base)));
+
+        match style {
+            PrefixStyle::Short => {
+                unique_prefixes(relative.collect(), 1, |p, len| abbreviate(p, len))
+            }
+            PrefixStyle::Basename => basenames(relative.collect()),
+            _ => unreachable!(),
+        }
+        .into_values()
+        .collect()
+    }
+
+    #[test]
+    fn test_short_paths() {
+        assert_eq!(
+            prefixes(PrefixStyle::Short),
+            vec![
+                "sr/ed/mod.rs",
+                "s/s/mod.rs",
+                "sp/ed/mod.rs",
+                ".g/ci.yml",
+                "README.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_basenames() {
+        assert_eq!(
+            prefixes(PrefixStyle::Basename),
+            vec!["mod.rs", "mod.rs~2", "mod.rs~3", "ci.yml", "README.md"]
+        );
+    }
+
+    #[test]
+    fn test_hash_prefixes() {
+        // The same path always gets the same letters, and retries differ
+        let code = hash_letters("src/main.rs", 0);
+        assert_eq!(code, hash_letters("src/main.rs", 0));
+        assert_ne!(code, hash_letters("src/main.rs", 1));
+        assert_eq!(code.len(), HASH_LETTERS);
+        assert!(code.bytes().all(|b| b.is_ascii_uppercase()));
+
+        // Files are hashed by their path in the repository
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize_utf8().unwrap();
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        fs::create_dir_all(root.join("repo/src")).unwrap();
+        fs::write(root.join("repo/src/main.rs"), "").unwrap();
+        assert_eq!(
+            hash_key(&root.join("repo/src/../src/main.rs")),
+            "src/main.rs"
+        );
+
+        // A collision goes to the key sorting first, whatever the aliases
+        let code = |key: &str, attempt: u32| {
+            let code = if key.starts_with('c') { "SAME" } else { key };
+            format!("{}{}", code, attempt)
+        };
+        let keys = |names: [&str; 3]| {
+            let keys = alias_iter().zip(names.iter().map(|n| n.to_string()));
+            hash_prefixes(keys.collect(), code)
+                .into_values()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(["c/b.rs", "a.rs", "c/a.rs"]),
+            ["SAME1", "a.rs0", "SAME0"]
+        );
+        assert_eq!(
+            keys(["c/a.rs", "c/b.rs", "a.rs"]),
+            ["SAME0", "SAME1", "a.rs0"]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::file_loader::{load_locations, read_input};
+use crate::locations::{LineSpan, Location};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use log::debug;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Load every result location in a SARIF log. Each line is annotated with the
+/// result's rule ID and message.
+pub fn load_from_sarif(
+    sarif_path: &Utf8PathBuf,
+    args: &Args,
+    filters: &Filters,
+) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
+    let content = read_input(sarif_path)?;
+    let locations = parse_sarif(&content)?;
+    let (matches, files) = load_locations(locations, args, filters)?;
+    Ok((matches, files, format!("SARIF: {}", sarif_path)))
+}
+
+fn parse_sarif(content: &str) -> Result<Vec<Location>> {
+    let log: Value = serde_json::from_str(content).context("parsing SARIF log")?;
+    let runs = log["runs"]
+        .as_array()
+        .context("SARIF log has no 'runs' array")?;
+
+    let mut locations = Vec::new();
+    for run in runs {
+        let base_ids = &run["originalUriBaseIds"];
+        for result in run["results"].as_array().into_iter().flatten() {
+            let rule = result["ruleId"]
+                .as_str()
+                .or_else(|| result["rule"]["id"].as_str());
+            let text = result["message"]["text"]
+                .as_str()
+                .or_else(|| result["message"]["markdown"].as_str())
+                .map(|text| substitute_arguments(text, &result["message"]["arguments"]));
+            let message = match (rule, text) {
+                (Some(rule), Some(text)) => Some(format!("[{}] {}", rule, text)),
+                (Some(rule), None) => Some(format!("[{}]", rule)),
+                (None, text) => text,
+            };
+
+            for loc in result["locations"].as_array().into_iter().flatten() {
+                let physical = &loc["physicalLocation"];
+                let artifact = &physical["artifactLocation"];
+                let Some(path) = artifact_path(artifact, base_ids) else {
+                    debug!("Skipping SARIF location without a file URI: {}", loc);
+                    continue;
+                };
+
+                // Results without a region apply to the whole file. Lines start at
+                // 1, so a startLine of 0, or one which isn't a number, is kept as
+                // line 0 for load_locations to report as invalid.
+                let region = &physical["region"];
+                let lines = match &region["startLine"] {
+                    Value::Null => LineSpan::All,
+                    start => {
+                        let start = start.as_u64().unwrap_or(0) as usize;
+                        let end = region["endLine"].as_u64().map_or(start, |e| e as usize);
+                        LineSpan::Range(start, end.max(start))
+                    }
+                };
+
+                locations.push(Location {
+                    path,
+                    lines,
+                    message: message.clone(),
+                });
+            }
+        }
+    }
+    Ok(locations)
+}
+
+/// Fill in a message's `{0}`, `{1}`, ... placeholders from its arguments.
+/// Doubled braces are literal, and placeholders without an argument are left
+/// as they are.
+fn substitute_arguments(text: &str, arguments: &Value) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(tail) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            result.push_str(&rest[..1]);
+            rest = tail;
+            continue;
+        }
+        let argument = rest
+            .strip_prefix('{')
+            .and_then(|r| r.split_once('}'))
+            .and_then(|(index, tail)| {
+                let index: usize = index.parse().ok()?;
+                Some((arguments[index].as_str()?, tail))
+            });
+        match argument {
+            Some((argument, tail)) => {
+                result.push_str(argument);
+                rest = tail;
+            }
+            None => {
+                result.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Resolve an artifactLocation to a path. Relative URIs may name a base in
+/// the run's originalUriBaseIds, like `%SRCROOT%`; otherwise they are left
+/// relative to the working directory.
+fn artifact_path(artifact: &Value, base_ids: &Value) -> Option<String> {
+    let uri = artifact["uri"].as_str()?;
+    let path = uri_to_path(uri)?;
+    if Utf8PathBuf::from(&path).is_absolute() {
+        return Some(path);
+    }
+
+    let base = artifact["uriBaseId"]
+        .as_str()
+        .and_then(|id| base_ids[id]["uri"].as_str())
+        .and_then(uri_to_path);
+    match base {
+        Some(base) => Some(Utf8PathBuf::from(base).join(path).into_string()),
+        None => Some(path),
+    }
+}
+
+/// Convert a file URI or relative reference to a path, decoding %-escapes.
+/// Non-file schemes can't be edited, so they return None.
+fn uri_to_path(uri: &str) -> Option<String> {
+    let path = match uri.split_once("://") {
+        Some(("file", rest)) => {
+            // Drop the authority, usually empty or "localhost"
+            let start = rest.find('/').unwrap_or(rest.len());
+            &rest[start..]
+        }
+        Some(_) => return None,
+        None => uri,
+    };
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sarif() {
+        let log = r#"{
+          "version": "2.1.0",
+          "runs": [{
+            "originalUriBaseIds": { "SRCROOT": { "uri": "file:///work/repo/" } },
+            "results": [
+              {
+                "ruleId": "no-unused-vars",
+                "message": { "text": "'x' is unused" },
+                "locations": [{ "physicalLocation": {
+                  "artifactLocation": { "uri": "src/my%20file.js", "uriBaseId": "SRCROOT" },
+                  "region": { "startLine": 3, "startColumn": 7 }
+                }}]
+              },
+              {
+                "ruleId": "CWE-79",
+                "message": { "text": "XSS" },
+                "locations": [{ "physicalLocation": {
+                  "artifactLocation": { "uri": "file:///abs/page.html" },
+                  "region": { "startLine": 10, "endLine": 12 }
+                }}]
+              },
+              {
+                "ruleId": "js/unused",
+                "message": { "text": "'{0}' is unused in {1}, {{ok}} {2}", "arguments": ["y", "f"] },
+                "locations": [{ "physicalLocation": {
+                  "artifactLocation": { "uri": "b.js" },
+                  "region": { "startLine": 0 }
+                }}]
+              },
+              {
+                "ruleId": "remote",
+                "locations": [{ "physicalLocation": {
+                  "artifactLocation": { "uri": "https://example.com/a.js" }
+                }}]
+              }
+            ]
+          }]
+        }"#;
+
+        let locations = parse_sarif(log).unwrap();
+        assert_eq!(
+            locations,
+            vec![
+                Location {
+                    path: "/work/repo/src/my file.js".to_string(),
+                    lines: LineSpan::Range(3, 3),
+                    message: Some("[no-unused-vars] 'x' is unused".to_string()),
+                },
+                Location {
+                    path: "/abs/page.html".to_string(),
+                    lines: LineSpan::Range(10, 12),
+                    message: Some("[CWE-79] XSS".to_string()),
+                },
+                Location {
+                    path: "b.js".to_string(),
+                    lines: LineSpan::Range(0, 0),
+                    message: Some("[js/unused] 'y' is unused in f, {ok} {2}".to_string()),
+                },
+            ]
+        );
+    }
+}
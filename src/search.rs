@@ -1,26 +1,146 @@
-use crate::{alias_iter, Args, FileAlias, FileInfo, MatchLine};
+use crate::{Args, FileAlias, FileInfo, Filters, MatchLine, alias_iter, case_variants};
 use anyhow::{Context, Result};
 use camino::Utf8PathBuf;
+use itertools::Itertools;
 use log::debug;
-use regex::Regex;
-use shellexpand;
-use std::collections::BTreeMap;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::process::{exit, Command};
+use std::process::{Command, exit};
+
+/// A match found on a line by one of okapi's own matchers
+#[derive(Debug, PartialEq)]
+pub struct LineHit {
+    /// 1-indexed byte column, like ripgrep's --column
+    pub column: usize,
+    /// Describes what was found, shown as an annotation in the buffer
+    pub note: Option<String>,
+}
 
 pub fn run_ripgrep_search(
     args: &Args,
+    filters: &Filters,
 ) -> Result<(Vec<MatchLine>, BTreeMap<FileAlias, FileInfo>, String)> {
-    let pattern = args
-        .pattern
-        .as_ref()
-        .context("Pattern required for search")?;
+    let (pattern, label) = if let Some(ref word) = args.case_variants {
+        let variants = case_variants::variants(word);
+        let names: Vec<&str> = variants.iter().map(|(_, v)| v.as_str()).collect();
+        (
+            case_variants::search_pattern(&variants),
+            format!("Case variants: {}", names.join(", ")),
+        )
+    } else {
+        let pattern = args
+            .pattern
+            .clone()
+            .context("Pattern required for search")?;
+        let label = format!("Regex: {}", pattern);
+        (pattern, label)
+    };
+    let mut cmd = Command::new("rg");
+    cmd.args(["-n", "--ignore-files", "--column", "--no-heading", &pattern]);
+    cmd.args(search_paths(args));
+
+    for (enabled, flag) in [
+        (args.ignore_case, "--ignore-case"),
+        (args.smart_case, "--smart-case"),
+        (args.fixed_strings, "--fixed-strings"),
+        (args.word_regexp, "--word-regexp"),
+        (args.multiline, "--multiline"),
+    ] {
+        if enabled {
+            cmd.arg(flag);
+        }
+    }
+    if !args.extra_args.is_empty() {
+        cmd.args(&args.extra_args);
+    }
+
+    let stdout = run_rg(cmd)?;
+    let matches = parse_rg_output(&stdout, args, filters)?;
+    let (files, mut match_lines) = finalize_search_data(matches, HashMap::new(), args)?;
+
+    if let Some(ref word) = args.case_variants {
+        case_variants::tag_variants(&mut match_lines, word)?;
+        if let Some(ref new_name) = args.replace {
+            case_variants::prefill_replacements(&mut match_lines, word, new_name)?;
+        }
+    }
+
+    Ok((match_lines, files, label))
+}
+
+/// Run a search which ripgrep can't do itself. Ripgrep only lists the files
+/// to search, honoring ignore files and extra args like `--type`, then
+/// `matcher` is run over every line. Hits outside --columns are discarded.
+pub fn scan_files<F>(
+    args: &Args,
+    filters: &Filters,
+    matcher: F,
+) -> Result<(BTreeMap<FileAlias, FileInfo>, Vec<MatchLine>)>
+where
+    F: Fn(&str) -> Vec<LineHit> + Sync,
+{
     let mut cmd = Command::new("rg");
-    cmd.args(["-n", "--ignore-files", "--column", "--no-heading", pattern]);
+    cmd.args(["--files", "--ignore-files"]);
+    cmd.args(search_paths(args));
+    cmd.args(&args.extra_args);
+    let stdout = run_rg(cmd)?;
+
+    let paths: Vec<&str> = stdout.lines().collect();
+    let scanned: Vec<_> = paths
+        .into_par_iter()
+        .filter_map(|path| {
+            // Binary and non-UTF-8 files can't be edited, so skip them
+            let content = fs::read_to_string(path).ok()?;
+            let hits: Vec<_> = content
+                .split_terminator('\n')
+                .enumerate()
+                .filter_map(|(idx, line)| {
+                    let hits: Vec<LineHit> = matcher(line)
+                        .into_iter()
+                        .filter(|h| filters.keeps_column(h.column))
+                        .collect();
+                    if hits.is_empty() || !filters.keeps_content(line) {
+                        return None;
+                    }
+                    let notes: Vec<String> =
+                        hits.into_iter().filter_map(|h| h.note).unique().collect();
+                    Some((idx + 1, line.to_string(), notes))
+                })
+                .collect();
+            (!hits.is_empty()).then(|| (Utf8PathBuf::from(path), content, hits))
+        })
+        .collect();
+
+    let mut notes = HashMap::new();
+    let mut contents = HashMap::new();
+    let mut matches = Vec::new();
+    for (path, content, hits) in scanned {
+        for (lineno, line, found) in hits {
+            debug!("Found {}:{} {:?}", path, lineno, found);
+            if !found.is_empty() {
+                notes.insert(
+                    (path.clone(), lineno),
+                    format!("found: {}", found.join(", ")),
+                );
+            }
+            matches.push((path.clone(), lineno, line));
+        }
+        contents.insert(path, content);
+    }
+    sort_and_truncate(&mut matches, filters.search_limit(args.max_count));
 
-    // 1. Process paths using camino and shellexpand
-    let paths: Vec<Utf8PathBuf> = args
-        .paths
+    let (files, mut match_lines) = finalize_search_data(matches, contents, args)?;
+    for m in match_lines.iter_mut() {
+        let key = (files[&m.alias].path.clone(), m.lineno);
+        m.annotation = notes.remove(&key);
+    }
+    Ok((files, match_lines))
+}
+
+/// Positional paths, with `~` expanded and --working-directory applied
+pub fn search_paths(args: &Args) -> Vec<Utf8PathBuf> {
+    args.paths
         .iter()
         .map(|p| {
             let expanded = shellexpand::tilde(p.as_str());
@@ -37,25 +157,17 @@ pub fn run_ripgrep_search(
                 expanded_path
             }
         })
-        .collect();
-
-    cmd.args(&paths);
-
-    if args.ignore_case {
-        cmd.arg("--ignore-case");
-    }
-    if !args.extra_args.is_empty() {
-        cmd.args(&args.extra_args);
-    }
+        .collect()
+}
 
+/// Run a ripgrep command and return its stdout. Exits the program if ripgrep fails.
+pub fn run_rg(mut cmd: Command) -> Result<String> {
     debug!("Running `{:?}`", &cmd);
 
-    // 2. Execute command
     let output = cmd
         .output()
         .context("failed to run ripgrep (is rg installed?)")?;
 
-    // 3. Handle ripgrep errors
     if !output.status.success() {
         // Ripgrep exit code 1 means "no matches found".
         // Any other non-zero code is a real error (invalid regex, etc.)
@@ -68,45 +180,40 @@ pub fn run_ripgrep_search(
         }
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let matches = parse_rg_output(&stdout, args)?;
-    let (files, match_lines) = finalize_search_data(matches, args)?;
-
-    Ok((match_lines, files, format!("Regex: {}", pattern)))
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
-fn parse_column_range(col_str: &str) -> Result<Vec<usize>> {
-    let mut s = col_str.to_string();
-    // Handle the shorthand ".." by providing boundaries
-    if s.starts_with("..") {
-        s.insert(0, '1');
-    }
-    if s.ends_with("..") {
-        s.push_str("1024");
-    }
+pub fn parse_column_range(col_str: &str) -> Result<Vec<usize>> {
+    // Ranges may be separated by ',' or ';'. Handle the shorthand ".." on each
+    // one by providing boundaries
+    let s = col_str
+        .split([',', ';'])
+        .map(|range| {
+            let mut range = range.trim().to_string();
+            if range.starts_with("..") {
+                range.insert(0, '1');
+            }
+            if range.ends_with("..") {
+                range.push_str("1024");
+            }
+            range
+        })
+        .collect::<Vec<_>>()
+        .join(",");
 
     // range_parser returns a Vec of all numbers included in the range(s)
     range_parser::parse_with::<usize>(&s, ",", "..").context("invalid column range")
 }
 
-fn parse_rg_output(stdout: &str, args: &Args) -> Result<Vec<(Utf8PathBuf, usize, String)>> {
+fn parse_rg_output(
+    stdout: &str,
+    args: &Args,
+    filters: &Filters,
+) -> Result<Vec<(Utf8PathBuf, usize, String)>> {
     let mut results = Vec::new();
-    let valid_columns = args
-        .columns
-        .as_ref()
-        .map(|s| parse_column_range(s))
-        .transpose()?;
-    let exclude_res: Vec<Regex> = args
-        .exclude
-        .iter()
-        .map(|p| {
-            regex::RegexBuilder::new(p)
-                .case_insensitive(args.ignore_case)
-                .build()
-        })
-        .collect::<Result<Vec<_>, _>>()?;
 
-    for line in stdout.lines() {
+    // Split on '\n' alone, so that lines of CRLF files keep their '\r'
+    for line in stdout.split_terminator('\n') {
         let parts: Vec<&str> = line.splitn(4, ':').collect();
         if parts.len() < 4 {
             continue;
@@ -115,18 +222,15 @@ fn parse_rg_output(stdout: &str, args: &Args) -> Result<Vec<(Utf8PathBuf, usize,
         let (path_str, line_str, col_str, content) = (parts[0], parts[1], parts[2], parts[3]);
         let col_no = col_str.parse::<usize>()?;
 
-        // Check if the current column is in the allowed set
-        if let Some(ref allowed) = valid_columns {
-            if !allowed.contains(&col_no) {
-                debug!(
-                    "Excluding {}:{} (col {}) - outside range",
-                    path_str, line_str, col_no
-                );
-                continue;
-            }
+        if !filters.keeps_column(col_no) {
+            debug!(
+                "Excluding {}:{} (col {}) - outside range",
+                path_str, line_str, col_no
+            );
+            continue;
         }
 
-        if exclude_res.iter().any(|re| re.is_match(content)) {
+        if !filters.keeps_content(content) {
             continue;
         }
         results.push((
@@ -136,15 +240,23 @@ fn parse_rg_output(stdout: &str, args: &Args) -> Result<Vec<(Utf8PathBuf, usize,
         ));
     }
 
+    sort_and_truncate(&mut results, filters.search_limit(args.max_count));
+    Ok(results)
+}
+
+/// Order matches by path and line number, then cap them at --max-count
+pub fn sort_and_truncate(results: &mut Vec<(Utf8PathBuf, usize, String)>, max_count: usize) {
     results.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
-    if results.len() > args.max_count {
-        results.truncate(args.max_count);
+    if results.len() > max_count {
+        results.truncate(max_count);
     }
-    Ok(results)
 }
 
-fn finalize_search_data(
+/// Give each matched file an alias and load it. `contents` holds any files
+/// which were already read while searching, keyed by their path in `matches`.
+pub fn finalize_search_data(
     matches: Vec<(Utf8PathBuf, usize, String)>,
+    mut contents: HashMap<Utf8PathBuf, String>,
     args: &Args,
 ) -> Result<(BTreeMap<FileAlias, FileInfo>, Vec<MatchLine>)> {
     let mut files = BTreeMap::new();
@@ -155,7 +267,7 @@ fn finalize_search_data(
         if path_to_alias.contains_key(path) {
             continue;
         }
-        let alias = aliases.next().context("exhausted 3-letter aliases")?;
+        let alias = aliases.next().expect("aliases never run out");
 
         let full_path = args
             .working_directory
@@ -163,8 +275,12 @@ fn finalize_search_data(
             .map(|wd| wd.join(path))
             .unwrap_or_else(|| path.clone());
 
-        let content =
-            fs::read_to_string(&full_path).with_context(|| format!("reading {}", full_path))?;
+        let content = match contents.remove(path) {
+            Some(content) => content,
+            None => {
+                fs::read_to_string(&full_path).with_context(|| format!("reading {}", full_path))?
+            }
+        };
         let mtime = fs::metadata(&full_path)?.modified()?;
 
         path_to_alias.insert(path.clone(), alias);
@@ -184,11 +300,7 @@ fn finalize_search_data(
         .into_iter()
         .map(|(path, lineno, content)| {
             let alias = *path_to_alias.get(&path).expect("path must have alias");
-            MatchLine {
-                alias,
-                lineno,
-                original_content: content,
-            }
+            MatchLine::new(alias, lineno, content)
         })
         .collect();
 
@@ -198,6 +310,8 @@ fn finalize_search_data(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::escape;
+    use clap::Parser;
 
     #[test]
     fn test_column_range_expansion() {
@@ -210,15 +324,31 @@ mod tests {
 
         // Shorthand end
         let end = parse_column_range("198..").unwrap();
-        assert_eq!(end, vec![198, 199, 200]);
+        assert_eq!(end[..3], [198, 199, 200]);
+        assert_eq!(end.last(), Some(&1024));
 
         // Multiple ranges
         let multi = parse_column_range("1..2;5..6").unwrap();
         assert_eq!(multi, vec![1, 2, 5, 6]);
+
+        // Shorthand on both ends of a list
+        let open = parse_column_range("..2,1023..").unwrap();
+        assert_eq!(open, vec![1, 2, 1023, 1024]);
     }
 
     #[test]
     fn test_invalid_range() {
         assert!(parse_column_range("abc").is_err());
     }
+
+    #[test]
+    fn test_crlf_lines_keep_their_cr() {
+        let args = Args::parse_from(["okapi", "x"]);
+        let filters = Filters::from_args(&args).unwrap();
+        let stdout = "b.txt:2:1:x = 1\r\na.txt:1:3:a x\n";
+        let results = parse_rg_output(stdout, &args, &filters).unwrap();
+        assert_eq!(results[0], ("a.txt".into(), 1, "a x".to_string()));
+        assert_eq!(results[1], ("b.txt".into(), 2, "x = 1\r".to_string()));
+        assert_eq!(escape::encode(&results[1].2, &[]), "x = 1\\r");
+    }
 }
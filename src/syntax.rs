@@ -0,0 +1,240 @@
+use crate::formats::Separator;
+use crate::{FileAlias, FileInfo};
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// Editors which okapi can write a buffer syntax definition for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SyntaxKind {
+    /// A vim/neovim syntax file
+    Vim,
+    /// A TextMate grammar in JSON, as used by VS Code and Sublime Text
+    Textmate,
+}
+
+/// A source language which buffer content can be highlighted as
+#[derive(Debug, PartialEq, Eq)]
+pub struct Language {
+    /// The vim filetype, also written to the buffer header
+    pub name: &'static str,
+    /// The TextMate scope of the language's grammar
+    pub scope: &'static str,
+    pub extensions: &'static [&'static str],
+}
+
+const fn lang(
+    name: &'static str,
+    scope: &'static str,
+    extensions: &'static [&'static str],
+) -> Language {
+    Language {
+        name,
+        scope,
+        extensions,
+    }
+}
+
+pub const LANGUAGES: &[Language] = &[
+    lang("c", "source.c", &["c", "h"]),
+    lang(
+        "cpp",
+        "source.cpp",
+        &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+    ),
+    lang("cs", "source.cs", &["cs"]),
+    lang("css", "source.css", &["css"]),
+    lang("go", "source.go", &["go"]),
+    lang("html", "text.html.basic", &["htm", "html"]),
+    lang("java", "source.java", &["java"]),
+    lang("javascript", "source.js", &["cjs", "js", "jsx", "mjs"]),
+    lang("json", "source.json", &["json"]),
+    lang("kotlin", "source.kotlin", &["kt", "kts"]),
+    lang("lua", "source.lua", &["lua"]),
+    lang("markdown", "text.html.markdown", &["md", "markdown"]),
+    lang("php", "source.php", &["php"]),
+    lang("python", "source.python", &["py", "pyi"]),
+    lang("ruby", "source.ruby", &["rb"]),
+    lang("rust", "source.rust", &["rs"]),
+    lang("sh", "source.shell", &["bash", "sh", "zsh"]),
+    lang("sql", "source.sql", &["sql"]),
+    lang("swift", "source.swift", &["swift"]),
+    lang("toml", "source.toml", &["toml"]),
+    lang("typescript", "source.ts", &["ts", "mts", "cts"]),
+    lang("typescriptreact", "source.tsx", &["tsx"]),
+    lang("yaml", "source.yaml", &["yaml", "yml"]),
+];
+
+/// The language shared by every file in the session, if there is one
+pub fn session_language(files: &BTreeMap<FileAlias, FileInfo>) -> Option<&'static Language> {
+    files
+        .values()
+        .map(|f| {
+            let ext = f.full_path.extension()?.to_lowercase();
+            LANGUAGES
+                .iter()
+                .find(|l| l.extensions.contains(&ext.as_str()))
+        })
+        .all_equal_value()
+        .ok()
+        .flatten()
+}
+
+/// The syntax definition for `kind`, ready to be saved where the editor looks
+/// for it. The gutter is matched by `separator`, so it must be the one the
+/// buffers are written with.
+pub fn definition(kind: SyntaxKind, separator: Separator) -> String {
+    match kind {
+        SyntaxKind::Vim => vim_syntax(separator),
+        SyntaxKind::Textmate => serde_json::to_string_pretty(&textmate_grammar(separator))
+            .expect("grammar is valid JSON"),
+    }
+}
+
+/// A vim syntax script for a single buffer, which okapi saves beside it and
+/// sources when the editor is vim, so its content is highlighted with no setup
+pub fn companion(separator: Separator) -> String {
+    format!(
+        "syntax clear\nunlet! b:current_syntax\n\n{}",
+        vim_syntax(separator)
+    )
+}
+
+/// `VIM_SYNTAX` with its separator class filled in. In a vim collection only
+/// ']' and '^' are special, and '"' ends the pattern; '\\' and '-' can't be
+/// separators.
+fn vim_syntax(separator: Separator) -> String {
+    let chars: String = separator
+        .chars()
+        .iter()
+        .map(|&c| match c {
+            ']' | '^' | '"' => format!("\\{}", c),
+            c => c.to_string(),
+        })
+        .collect();
+    VIM_SYNTAX.replace("{class}", &format!("[{}+-]", chars))
+}
+
+const VIM_SYNTAX: &str = r#"" Vim syntax file for okapi editing buffers
+" Save as ~/.vim/syntax/okapi.vim or ~/.config/nvim/syntax/okapi.vim, and add
+" this to your vimrc or init.vim:
+"   autocmd BufRead,BufNewFile *.okapi.txt setfiletype okapi
+
+if exists("b:current_syntax")
+  finish
+endif
+
+" Highlight the content as the language named in the header, if there is one
+let s:lang = ''
+for s:line in getline(1, 10)
+  let s:lang = matchstr(s:line, '^# Language: \zs\w\+')
+  if s:lang != ''
+    break
+  endif
+endfor
+if s:lang != '' && !empty(globpath(&runtimepath, 'syntax/' . s:lang . '.vim'))
+  execute 'syntax include @okapiLanguage syntax/' . s:lang . '.vim'
+  unlet! b:current_syntax
+endif
+
+syntax match okapiComment "^#.*$"
+syntax match okapiGutter "^\s*\S.\{-}\s\+\d\+\s\+{class}" contains=okapiShade nextgroup=okapiContent
+syntax match okapiShade "{class}" contained
+syntax match okapiContent ".*$" contained contains=@okapiLanguage
+
+highlight default link okapiComment Comment
+highlight default link okapiGutter LineNr
+highlight default link okapiShade NonText
+
+let b:current_syntax = "okapi"
+"#;
+
+fn textmate_grammar(separator: Separator) -> Value {
+    let gutter = format!(r"^(\s*\S.*?\s+\d+\s+)({})(.*)$", separator.marker_class());
+    let line = |content: Value| {
+        json!({
+            "match": gutter,
+            "captures": {
+                "1": { "name": "comment.other.gutter.okapi" },
+                "2": { "name": "punctuation.separator.okapi" },
+                "3": content,
+            }
+        })
+    };
+    let comment = json!({ "match": "^#.*$", "name": "comment.line.number-sign.okapi" });
+
+    // A buffer whose header names a language is highlighted as that language
+    // until the alias footer
+    let mut patterns: Vec<Value> = LANGUAGES
+        .iter()
+        .map(|l| {
+            json!({
+                "begin": format!("^# Language: {}$", l.name),
+                "end": "^(?=# --- File Aliases ---)",
+                "beginCaptures": { "0": { "name": "comment.line.number-sign.okapi" } },
+                "patterns": [
+                    comment,
+                    line(json!({ "patterns": [{ "include": l.scope }] })),
+                ],
+            })
+        })
+        .collect();
+    patterns.push(comment.clone());
+    patterns.push(line(json!({ "name": "meta.content.okapi" })));
+
+    json!({
+        "$schema": "https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json",
+        "name": "okapi",
+        "scopeName": "text.okapi",
+        "fileTypes": ["okapi.txt"],
+        "patterns": patterns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files;
+
+    #[test]
+    fn test_session_language() {
+        let name = |paths: &[&str]| {
+            let files: Vec<(&str, &str)> = paths.iter().map(|p| (*p, "")).collect();
+            session_language(&test_files(&files)).map(|l| l.name)
+        };
+        assert_eq!(name(&["src/main.rs", "src/lib.RS"]), Some("rust"));
+        assert_eq!(name(&["a.ts", "b.mts"]), Some("typescript"));
+        assert_eq!(name(&["src/main.rs", "build.py"]), None);
+        assert_eq!(name(&["Makefile"]), None);
+    }
+
+    #[test]
+    fn test_separator_class() {
+        let vim = definition(
+            SyntaxKind::Vim,
+            Separator {
+                heavy: '▓',
+                light: '░',
+            },
+        );
+        assert!(vim.contains(r#"syntax match okapiShade "[▓░+-]" contained"#));
+        let vim = definition(
+            SyntaxKind::Vim,
+            Separator {
+                heavy: ']',
+                light: '"',
+            },
+        );
+        assert!(vim.contains(r#"syntax match okapiShade "[\]\"+-]" contained"#));
+
+        let textmate = definition(
+            SyntaxKind::Textmate,
+            Separator {
+                heavy: '|',
+                light: ':',
+            },
+        );
+        assert!(textmate.contains(r#"([\\|:+-])"#));
+    }
+}